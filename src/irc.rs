@@ -1,3 +1,18 @@
 pub mod protocol;
+pub mod badge;
+pub mod cheer;
+pub mod ctcp;
 pub mod client;
+mod connection;
+pub mod emotes;
+pub mod event;
+mod membership;
+pub mod notice;
+pub mod ratelimit;
+pub mod reconnect;
+pub mod richtext;
+pub mod roomstate;
+pub mod transport;
+pub mod usernotice;
+pub mod userstate;
 pub mod utils;
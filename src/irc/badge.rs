@@ -0,0 +1,112 @@
+use crate::irc::protocol::Message;
+
+/// One entry of the `badges` or `badge-info` tag, e.g. `subscriber/12`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Badge {
+    pub name: String,
+    pub version: String,
+}
+
+fn parse_badges(tag: Option<&String>) -> Vec<Badge> {
+    let Some(tag) = tag else {
+        return vec![];
+    };
+    tag.split(',')
+        .filter(|badge| !badge.is_empty())
+        .map(|badge| {
+            let (name, version) = badge.split_once('/').unwrap_or((badge, ""));
+            Badge {
+                name: name.into(),
+                version: version.into(),
+            }
+        })
+        .collect()
+}
+
+impl Message {
+    pub fn badges(&self) -> Vec<Badge> {
+        parse_badges(self.tags.get("badges"))
+    }
+
+    /// Details for some of the badges, `subscriber/12` here holds the exact months.
+    pub fn badge_info(&self) -> Vec<Badge> {
+        parse_badges(self.tags.get("badge-info"))
+    }
+
+    pub fn has_badge(&self, name: &str) -> bool {
+        self.badges().iter().any(|badge| badge.name == name)
+    }
+
+    pub fn is_broadcaster(&self) -> bool {
+        self.has_badge("broadcaster")
+    }
+
+    pub fn is_moderator(&self) -> bool {
+        self.has_badge("moderator") || self.tags.get("mod").is_some_and(|v| v == "1")
+    }
+
+    pub fn is_vip(&self) -> bool {
+        self.has_badge("vip") || self.tags.get("vip").is_some_and(|v| v == "1")
+    }
+
+    pub fn is_subscriber(&self) -> bool {
+        // founders keep the founder badge instead of the subscriber badge
+        self.has_badge("subscriber") || self.has_badge("founder") || self.tags.get("subscriber").is_some_and(|v| v == "1")
+    }
+
+    /// Exact months from `badge-info`, the `subscriber` badge version is only the tier of the badge image.
+    pub fn subscriber_months(&self) -> Option<u32> {
+        self.badge_info().iter()
+            .find(|badge| badge.name == "subscriber" || badge.name == "founder")
+            .and_then(|badge| badge.version.parse().ok())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::protocol::parse_line;
+
+    #[test]
+    fn test_badges() {
+        let line = "@badge-info=subscriber/14;badges=broadcaster/1,subscriber/12,glhf-pledge/1;mod=0 :ronni!ronni@ronni.tmi.twitch.tv PRIVMSG #ronni :hi";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.badges(), vec![
+            Badge { name: "broadcaster".into(), version: "1".into() },
+            Badge { name: "subscriber".into(), version: "12".into() },
+            Badge { name: "glhf-pledge".into(), version: "1".into() },
+        ]);
+        assert!(msg.is_broadcaster());
+        assert!(msg.is_subscriber());
+        assert!(!msg.is_moderator());
+        assert!(!msg.is_vip());
+        assert_eq!(msg.subscriber_months(), Some(14));
+    }
+
+    #[test]
+    fn test_badges_empty() {
+        let line = "@badge-info=;badges=;mod=0;subscriber=0 :tmi.twitch.tv USERSTATE #dallas";
+        let msg = parse_line(line).unwrap();
+        assert!(msg.badges().is_empty());
+        assert!(!msg.is_subscriber());
+        assert_eq!(msg.subscriber_months(), None);
+        assert!(parse_line("PING :tmi.twitch.tv").unwrap().badges().is_empty());
+    }
+
+    #[test]
+    fn test_moderator_and_vip() {
+        let msg = parse_line("@badges=moderator/1,founder/0;badge-info=founder/3 :tmi.twitch.tv USERSTATE #dallas").unwrap();
+        assert!(msg.is_moderator());
+        assert!(msg.is_subscriber());
+        assert_eq!(msg.subscriber_months(), Some(3));
+
+        let msg = parse_line("@badges=vip/1;vip=1 :tmi.twitch.tv USERSTATE #dallas").unwrap();
+        assert!(msg.is_vip());
+        assert!(!msg.is_moderator());
+
+        let msg = parse_line("@badges=;mod=0;vip=0 :tmi.twitch.tv USERSTATE #dallas").unwrap();
+        assert!(!msg.is_vip());
+        assert!(!msg.is_moderator());
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use serde_json::Value;
+use crate::irc::emotes::LoadError;
+use crate::irc::protocol::Message;
+
+// the global cheermotes, channels can add their own
+const DEFAULT_PREFIXES: [&str; 32] = [
+    "Cheer", "DoodleCheer", "BibleThump", "cheerwhal", "Corgo", "Scoops", "uni", "ShowLove", "Party",
+    "SeemsGood", "Pride", "Kappa", "FrankerZ", "HeyGuys", "DansGame", "EleGiggle", "TriHard", "Kreygasm",
+    "4Head", "SwiftRage", "NotLikeThis", "FailFish", "VoHiYo", "PJSalt", "MrDestructoid", "bday",
+    "RIPCheer", "Shamrock", "BitBoss", "Streamlabs", "Muxy", "HolidayCheer",
+];
+
+// the smallest amount of each tier, each tier has its own image
+const TIERS: [u32; 5] = [10000, 5000, 1000, 100, 1];
+
+#[derive(Debug, PartialEq)]
+pub enum CheerError {
+    // no `bits` tag, the message isn't a cheer
+    NoBits,
+    // the cheer tokens don't add up to the `bits` tag, `found` stops at u32::MAX
+    Mismatch { tagged: u32, found: u32 },
+}
+
+impl Display for CheerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheerError::NoBits => write!(f, "message has no bits tag"),
+            CheerError::Mismatch { tagged, found } => write!(f, "bits tag says {} but the cheers add up to {}", tagged, found),
+        }
+    }
+}
+
+impl std::error::Error for CheerError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheerToken {
+    // as written in the message
+    pub prefix: String,
+    pub bits: u32,
+    // 1, 100, 1000, 5000 or 10000
+    pub tier: u32,
+}
+
+pub fn tier(bits: u32) -> u32 {
+    TIERS.iter().copied().find(|min| bits >= *min).unwrap_or(1)
+}
+
+/// The prefixes that make a word like `Cheer100` a cheer, matched ignoring case.
+#[derive(Debug, Clone)]
+pub struct Cheermotes {
+    prefixes: Vec<String>,
+}
+
+impl Default for Cheermotes {
+    fn default() -> Self {
+        Cheermotes::new(&DEFAULT_PREFIXES)
+    }
+}
+
+impl Cheermotes {
+    pub fn new(prefixes: &[&str]) -> Cheermotes {
+        let mut prefixes = prefixes.iter().map(|p| p.to_lowercase()).collect::<Vec<_>>();
+        // longest first, so DoodleCheer isn't taken for a word starting with some shorter prefix
+        prefixes.sort_by_key(|p| std::cmp::Reverse(p.len()));
+        Cheermotes { prefixes }
+    }
+
+    /// The list shared by every message that doesn't bring its own.
+    pub(crate) fn global() -> &'static Cheermotes {
+        static GLOBAL: OnceLock<Cheermotes> = OnceLock::new();
+        GLOBAL.get_or_init(Cheermotes::default)
+    }
+
+    /// Accepts the response of the Helix `bits/cheermotes` endpoint or a plain list of prefixes.
+    pub fn from_json(json: &str) -> Result<Cheermotes, LoadError> {
+        let value: Value = serde_json::from_str(json)?;
+        let entries = value.get("data").unwrap_or(&value).as_array()
+            .ok_or_else(|| LoadError::Format("expected a list of cheermotes".into()))?;
+        let mut prefixes = vec![];
+        for entry in entries {
+            let prefix = entry.as_str().or_else(|| entry.get("prefix").and_then(|p| p.as_str()))
+                .ok_or_else(|| LoadError::Format(format!("cheermote without prefix: {}", entry)))?;
+            prefixes.push(prefix);
+        }
+        Ok(Cheermotes::new(&prefixes))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Cheermotes, LoadError> {
+        Cheermotes::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// A known prefix followed by only digits, e.g. `Cheer100` or `kappa5`.
+    pub fn parse(&self, word: &str) -> Option<CheerToken> {
+        self.prefixes.iter().find_map(|prefix| {
+            let (head, amount) = (word.get(..prefix.len())?, &word[prefix.len()..]);
+            if !head.eq_ignore_ascii_case(prefix) || amount.is_empty() || !amount.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            let bits = amount.parse().ok().filter(|bits| *bits > 0)?;
+            Some(CheerToken {
+                prefix: head.into(),
+                bits,
+                tier: tier(bits),
+            })
+        })
+    }
+}
+
+impl Message {
+    /// The `bits` tag, the total amount cheered with this message.
+    pub fn bits(&self) -> Option<u32> {
+        self.tags.get("bits").and_then(|bits| bits.parse().ok())
+    }
+
+    /// The cheer tokens in the text, which must add up to the `bits` tag.
+    pub fn cheers(&self, cheermotes: &Cheermotes) -> Result<Vec<CheerToken>, CheerError> {
+        let tagged = self.bits().ok_or(CheerError::NoBits)?;
+        let text = self.text().unwrap_or("");
+        let tokens = text.split_whitespace().filter_map(|word| cheermotes.parse(word)).collect::<Vec<_>>();
+        match tokens.iter().try_fold(0u32, |sum, token| sum.checked_add(token.bits)) {
+            Some(found) if found == tagged => Ok(tokens),
+            Some(found) => Err(CheerError::Mismatch { tagged, found }),
+            None => Err(CheerError::Mismatch { tagged, found: u32::MAX }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::protocol::parse_line;
+
+    #[test]
+    fn test_tier() {
+        assert_eq!(tier(1), 1);
+        assert_eq!(tier(99), 1);
+        assert_eq!(tier(100), 100);
+        assert_eq!(tier(4999), 1000);
+        assert_eq!(tier(5000), 5000);
+        assert_eq!(tier(250000), 10000);
+    }
+
+    #[test]
+    fn test_parse_token() {
+        let cheermotes = Cheermotes::default();
+        assert_eq!(cheermotes.parse("cheer1000"), Some(CheerToken { prefix: "cheer".into(), bits: 1000, tier: 1000 }));
+        assert_eq!(cheermotes.parse("4Head5").unwrap().prefix, "4Head");
+        assert_eq!(cheermotes.parse("DoodleCheer100").unwrap().prefix, "DoodleCheer");
+        assert_eq!(cheermotes.parse("Cheer"), None);
+        assert_eq!(cheermotes.parse("Cheer0"), None);
+        assert_eq!(cheermotes.parse("Cheer10x"), None);
+        assert_eq!(cheermotes.parse("abc100"), None);
+        assert_eq!(cheermotes.parse("Chéér100"), None);
+    }
+
+    #[test]
+    fn test_cheers() {
+        let cheermotes = Cheermotes::default();
+        let line = "@bits=1105;emotes= :nick!user@host PRIVMSG #channel :Cheer1000 Kappa100 great stream cheer5";
+        let tokens = parse_line(line).unwrap().cheers(&cheermotes).unwrap();
+        assert_eq!(tokens.iter().map(|t| (t.bits, t.tier)).collect::<Vec<_>>(), vec![(1000, 1000), (100, 100), (5, 1)]);
+
+        let line = "@bits=100 :nick!user@host PRIVMSG #channel :Cheer50";
+        let result = parse_line(line).unwrap().cheers(&cheermotes);
+        assert_eq!(result, Err(CheerError::Mismatch { tagged: 100, found: 50 }));
+
+        let line = ":nick!user@host PRIVMSG #channel :Cheer50";
+        assert_eq!(parse_line(line).unwrap().cheers(&cheermotes), Err(CheerError::NoBits));
+
+        // more than a u32 holds, even when the tag happens to be the largest one
+        let line = "@bits=4294967295 :nick!user@host PRIVMSG #channel :Cheer4294967295 Cheer1";
+        let result = parse_line(line).unwrap().cheers(&cheermotes);
+        assert_eq!(result, Err(CheerError::Mismatch { tagged: u32::MAX, found: u32::MAX }));
+    }
+
+    #[test]
+    fn test_load_cheermotes() {
+        let cheermotes = Cheermotes::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cheermotes.json")).unwrap();
+        assert!(cheermotes.parse("forsenCheer10").is_some());
+        assert!(cheermotes.parse("Kappa10").is_none());
+
+        let cheermotes = Cheermotes::from_json(r#"["Kappa"]"#).unwrap();
+        assert!(cheermotes.parse("Kappa10").is_some());
+        assert!(matches!(Cheermotes::from_json(r#"{"data": [{}]}"#), Err(LoadError::Format(_))));
+    }
+}
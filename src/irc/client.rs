@@ -1,12 +1,33 @@
-use std::{fmt, thread};
-use std::fmt::Formatter;
-use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::sync::mpsc::{channel, Receiver};
-use crate::irc::protocol::{Command, Message, parse_line};
+use std::{fmt, io, thread};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+use crate::irc::connection::{Connection, Session};
+use crate::irc::ctcp::{self, DELIMITER};
+use crate::irc::event::Event;
+use crate::irc::membership::Membership;
+use crate::irc::protocol::{Command, Message, ParseError, parse_line};
+use crate::irc::ratelimit::RateLimits;
+use crate::irc::reconnect::ReconnectPolicy;
+use crate::irc::roomstate::RoomState;
+use crate::irc::userstate::UserState;
+use crate::irc::transport::TlsConfig;
+use crate::irc::utils::{normalize_channel, random_unit, split_message};
 
-struct Secret {
-    value: String,
+const DEFAULT_HOST: &str = "irc.twitch.tv";
+const DEFAULT_CAPABILITIES: [&str; 3] = ["twitch.tv/membership", "twitch.tv/tags", "twitch.tv/commands"];
+// twitch silently drops longer messages
+const MAX_MESSAGE_LENGTH: usize = 500;
+// twitch refuses a message identical to the previous one within this window
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(30);
+// not displayed, but makes the message differ
+const DUPLICATE_BYPASS: &str = " \u{E0000}";
+
+#[derive(Clone)]
+pub(crate) struct Secret {
+    pub(crate) value: String,
 }
 
 impl fmt::Debug for Secret {
@@ -16,121 +37,975 @@ impl fmt::Debug for Secret {
 }
 
 #[derive(Debug)]
-pub struct Client {
-    token: Secret,
-    nickname: String,
-    receiver: Option<Receiver<Message>>,
-    pub stream: Option<TcpStream>,
+pub enum ClientError {
+    Io(io::Error),
+    // the NOTICE twitch sent for a bad or malformed token
+    Authentication(String),
+    // capabilities the server refused with CAP NAK
+    CapabilityRejected(Vec<String>),
+    Parse(ParseError, String),
+    InvalidUtf8(Vec<u8>),
+    NotConnected,
+    // anonymous clients can't send chat messages
+    ReadOnly,
+    // an argument to a send helper that can't be sent as is
+    InvalidInput(String),
+    // the server refused our message to this channel as a duplicate of the previous one
+    DuplicateMessage(String),
 }
 
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "I/O error: {}", e),
+            ClientError::Authentication(notice) => write!(f, "authentication failed: {}", notice),
+            ClientError::CapabilityRejected(caps) => write!(f, "capabilities rejected: {}", caps.join(" ")),
+            ClientError::Parse(e, line) => write!(f, "couldn't parse {:?}: {:?}", line, e),
+            ClientError::InvalidUtf8(bytes) => write!(f, "line is not valid UTF-8: {:?}", bytes),
+            ClientError::NotConnected => write!(f, "client is not connected"),
+            ClientError::ReadOnly => write!(f, "anonymous clients can't send messages"),
+            ClientError::InvalidInput(reason) => write!(f, "invalid input: {}", reason),
+            ClientError::DuplicateMessage(channel) => write!(f, "duplicate message to {} was not sent", channel),
+        }
+    }
+}
 
-impl Client {
-    pub fn new(token: &str, nickname: &str) -> Client {
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+/// What the connection thread learns and the client exposes.
+#[derive(Debug, Default)]
+pub(crate) struct SharedState {
+    // the initial channels plus those the server confirmed we joined, minus those we parted
+    pub(crate) channels: Vec<String>,
+    // acknowledged by the server during the last login
+    pub(crate) capabilities: Vec<String>,
+    // lines sent or re-joins scheduled but not written yet
+    pub(crate) queued: usize,
+    // per channel we are in, merged from every ROOMSTATE
+    pub(crate) room_states: HashMap<String, RoomState>,
+    // ours, from GLOBALUSERSTATE
+    pub(crate) global_user_state: Option<UserState>,
+    // ours per channel, from the latest USERSTATE there
+    pub(crate) user_states: HashMap<String, UserState>,
+    pub(crate) membership: Membership,
+}
+
+/// Everything a connection needs to (re-)establish a session. Built by `ClientBuilder`.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientConfig {
+    // None logs in anonymously
+    pub(crate) token: Option<Secret>,
+    pub(crate) nickname: String,
+    pub(crate) host: String,
+    // None picks 6697 with TLS and 6667 without
+    pub(crate) port: Option<u16>,
+    pub(crate) tls: Option<TlsConfig>,
+    pub(crate) capabilities: Vec<String>,
+    pub(crate) channels: Vec<String>,
+    // limits connecting and logging in, each
+    pub(crate) connect_timeout: Option<Duration>,
+    // no data from the server for this long counts as a lost connection
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) reconnect_policy: ReconnectPolicy,
+    // None sends as fast as the connection allows
+    pub(crate) rate_limits: Option<RateLimits>,
+    // ends every piece but the last of a split message
+    pub(crate) continuation_marker: Option<String>,
+    pub(crate) bypass_duplicates: bool,
+}
+
+impl ClientConfig {
+    pub(crate) fn port(&self) -> u16 {
+        self.port.unwrap_or(if self.tls.is_some() { 6697 } else { 6667 })
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientBuilder {
+    config: ClientConfig,
+}
+
+impl ClientBuilder {
+    pub fn new(token: &str, nickname: &str) -> ClientBuilder {
+        ClientBuilder::with_credentials(Some(Secret { value: token.into() }), nickname)
+    }
+
+    /// Read-only login without a token, as `justinfan` followed by a random number.
+    pub fn anonymous() -> ClientBuilder {
+        let number = 10000 + (random_unit() * 90000.0) as u32;
+        ClientBuilder::with_credentials(None, &format!("justinfan{}", number))
+    }
+
+    fn with_credentials(token: Option<Secret>, nickname: &str) -> ClientBuilder {
+        ClientBuilder {
+            config: ClientConfig {
+                token,
+                nickname: nickname.into(),
+                host: DEFAULT_HOST.into(),
+                port: None,
+                tls: None,
+                capabilities: DEFAULT_CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+                channels: vec![],
+                connect_timeout: Some(Duration::from_secs(10)),
+                // twitch pings about every 5 minutes
+                idle_timeout: Some(Duration::from_secs(360)),
+                reconnect_policy: ReconnectPolicy::default(),
+                rate_limits: Some(RateLimits::default()),
+                continuation_marker: None,
+                bypass_duplicates: false,
+            }
+        }
+    }
+
+    pub fn address(mut self, host: &str, port: u16) -> ClientBuilder {
+        self.config.host = host.into();
+        self.config.port = Some(port);
+        self
+    }
+
+    /// `None` connects in plaintext.
+    pub fn tls(mut self, tls: Option<TlsConfig>) -> ClientBuilder {
+        self.config.tls = tls;
+        self
+    }
+
+    /// Requested with `CAP REQ` before logging in. An empty list skips the request.
+    pub fn capabilities(mut self, capabilities: &[&str]) -> ClientBuilder {
+        self.config.capabilities = capabilities.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    /// Joined as soon as the server accepted the login.
+    pub fn channels(mut self, channels: &[&str]) -> ClientBuilder {
+        self.config.channels = channels.iter().map(|c| normalize_channel(c)).collect();
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> ClientBuilder {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> ClientBuilder {
+        self.config.idle_timeout = timeout;
+        self
+    }
+
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> ClientBuilder {
+        self.config.reconnect_policy = policy;
+        self
+    }
+
+    /// Sends wait in the client's queue until the limits allow them. `None` turns limiting off,
+    /// e.g. for verified bots.
+    pub fn rate_limits(mut self, limits: Option<RateLimits>) -> ClientBuilder {
+        self.config.rate_limits = limits;
+        self
+    }
+
+    /// Appended, after a space, to all but the last piece of a message too long for one PRIVMSG.
+    pub fn continuation_marker(mut self, marker: Option<&str>) -> ClientBuilder {
+        self.config.continuation_marker = marker.map(|m| m.into());
+        self
+    }
+
+    /// Varies a message that is identical to the previous one sent to the channel within 30 seconds
+    /// with an invisible character, so twitch doesn't refuse it.
+    pub fn bypass_duplicates(mut self, enabled: bool) -> ClientBuilder {
+        self.config.bypass_duplicates = enabled;
+        self
+    }
+
+    pub fn build(self) -> Client {
+        let shared = SharedState {
+            channels: self.config.channels.clone(),
+            ..Default::default()
+        };
         Client {
-            token: Secret { value: token.into() },
-            nickname: nickname.into(),
+            config: self.config,
             receiver: None,
-            stream: None,
+            outgoing: None,
+            shared: Arc::new(Mutex::new(shared)),
+            last_sent: Mutex::new(HashMap::new()),
         }
     }
+}
 
-    pub fn connect(&mut self) {
-        let (sender, receiver) = channel::<Message>();
+/// Whispers can't be sent over IRC anymore, they go through the Helix `POST /helix/whispers` endpoint.
+#[derive(Debug)]
+pub struct Client {
+    config: ClientConfig,
+    receiver: Option<Receiver<Event>>,
+    outgoing: Option<Sender<String>>,
+    shared: Arc<Mutex<SharedState>>,
+    // the last text sent per channel and when, for `bypass_duplicates`
+    last_sent: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+
+impl Client {
+    pub fn new(token: &str, nickname: &str) -> Client {
+        ClientBuilder::new(token, nickname).build()
+    }
+
+    pub fn builder(token: &str, nickname: &str) -> ClientBuilder {
+        ClientBuilder::new(token, nickname)
+    }
+
+    /// A client that can read chat without a token. Sending chat messages fails with `ClientError::ReadOnly`.
+    pub fn anonymous() -> Client {
+        ClientBuilder::anonymous().build()
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.config.token.is_none()
+    }
+
+    pub fn nickname(&self) -> &str {
+        &self.config.nickname
+    }
+
+    /// Blocks until the server accepted the login (001), rejected it, or `connect_timeout` passed.
+    /// Errors that happen after this returns are delivered as `Event::Error`.
+    pub fn connect(&mut self) -> Result<(), ClientError> {
+        let session = Session::establish(&self.config)?;
+        self.shared.lock().unwrap().capabilities = session.capabilities().to_vec();
+
+        let (sender, receiver) = channel::<Event>();
+        let (outgoing_sender, outgoing_receiver) = channel::<String>();
         self.receiver = Some(receiver);
+        self.outgoing = Some(outgoing_sender);
 
-        let mut stream = TcpStream::connect("irc.twitch.tv:6667").unwrap();
-        self.stream = Some(stream.try_clone().unwrap());
-
-        self.send_line("CAP REQ :twitch.tv/membership twitch.tv/tags twitch.tv/commands").unwrap();
-        self.send_line(&format!("PASS {}\r\n", self.token.value)).unwrap();
-        self.send_line(&format!("NICK {}\r\n", self.nickname)).unwrap();
-
-        thread::spawn(move || {
-            let mut vbuf: Vec<u8> = vec![];
-            'mainloop: loop {
-                let mut buf = [0u8; 8096];
-                let chunk;
-                match stream.read(&mut buf) {
-                    Ok(size) => {
-                        if size == 0 {
-                            println!("Stream read returned 0 bytes");
-                            break 'mainloop;
-                        }
-                        chunk = &buf[..size];
-                    }
-                    Err(e) => {
-                        println!("Failed to read from stream {:?}", e);
-                        break 'mainloop;
-                    }
-                }
-                vbuf.extend(chunk);
-
-                while let Some((pos, _)) = vbuf.iter().enumerate().find(|(_, c)| **c == 10) {
-                    let line_vec = vbuf[..pos].to_vec();
-                    vbuf = vbuf[pos + 1..].to_vec();
-                    if let Ok(line) = String::from_utf8(line_vec.clone()) {
-                        let final_line = line.trim_end_matches(|c| c == '\r' || c == '\n');
-                        match parse_line(final_line) {
-                            Ok(msg) => {
-                                match msg.command {
-                                    Command::Ping => {
-                                        println!("Ping? Pong!");
-                                        let write_result = stream.write(
-                                            format!("{}\r\n", msg.with_command(Command::Pong)).as_bytes()
-                                        );
-                                        if let Err(e) = write_result {
-                                            println!("Error writing to stream {:?}", e);
-                                            break 'mainloop;
-                                        }
-                                    }
-                                    _ => {
-                                        if let Err(e) = sender.send(msg) {
-                                            println!("Error sending to channel {:?}", e);
-                                            break 'mainloop;
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                println!("Error: {:?} - {:?}", e, final_line);
-                            }
-                        }
-                    } else {
-                        println!("*** Couldn't parse line {:?}", line_vec);
-                    }
-                }
-            }
-        });
+        let connection = Connection::new(self.config.clone(), sender, outgoing_receiver, self.shared.clone());
+
+        // on RECONNECT or a lost connection the connection thread swaps in a fresh session
+        // and re-joins every channel, the receiver stays the same.
+        thread::spawn(move || connection.run(session));
+        Ok(())
     }
 
-    pub fn send_line(&self, line: &str) -> Result<(), std::io::Error> {
-        if let Some(mut stream) = self.stream.as_ref() {
-            stream.write(format!("{}\r\n", line).as_bytes())?;
+    /// Queues a raw line. A trailing line ending is optional, any other CR or LF is rejected.
+    pub fn send_line(&self, line: &str) -> Result<(), ClientError> {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        check_line_breaks(line)?;
+        // twitch silently ignores chat messages from anonymous users
+        if self.is_anonymous() && parse_line(line).is_ok_and(|msg| msg.command == Command::Privmsg) {
+            return Err(ClientError::ReadOnly);
+        }
+        let outgoing = self.outgoing.as_ref().ok_or(ClientError::NotConnected)?;
+        // counted before sending, the connection thread may write it right away
+        self.shared.lock().unwrap().queued += 1;
+        outgoing.send(line.into()).map_err(|_| {
+            self.shared.lock().unwrap().queued -= 1;
+            ClientError::NotConnected
+        })
+    }
+
+    pub fn send(&self, msg: &Message) -> Result<(), ClientError> {
+        self.send_line(&format!("{}", msg))
+    }
+
+    pub fn join(&self, channel: &str) -> Result<(), ClientError> {
+        let channel = channel_param(channel)?;
+        self.send(&Message::new(Command::Join, &[&channel], None))
+    }
+
+    pub fn part(&self, channel: &str) -> Result<(), ClientError> {
+        let channel = channel_param(channel)?;
+        self.send(&Message::new(Command::Part, &[&channel], None))
+    }
+
+    /// Text over 500 characters is split into several messages.
+    pub fn privmsg(&self, channel: &str, text: &str) -> Result<(), ClientError> {
+        self.send_text(channel, ("", ""), text, None)
+    }
+
+    /// Sends `text` as a threaded reply to the message with the `id` tag `parent_msg_id`.
+    pub fn reply(&self, parent_msg_id: &str, channel: &str, text: &str) -> Result<(), ClientError> {
+        self.send_text(channel, ("", ""), text, Some(parent_msg_id))
+    }
+
+    /// `/me`, shown in the sender's color. Sent as a CTCP ACTION, which is what `/me` turns into anyway.
+    pub fn action(&self, channel: &str, text: &str) -> Result<(), ClientError> {
+        if text.contains(DELIMITER) {
+            return Err(ClientError::InvalidInput(format!("{:?} contains a CTCP delimiter", text)));
+        }
+        let (prefix, suffix) = ctcp::wrap("ACTION");
+        self.send_text(channel, (&prefix, &suffix), text, None)
+    }
+
+    // one PRIVMSG per piece, each wrapped in `(prefix, suffix)` (a CTCP ACTION)
+    fn send_text(&self, channel: &str, wrap: (&str, &str), text: &str, parent_msg_id: Option<&str>) -> Result<(), ClientError> {
+        let channel = channel_param(channel)?;
+        let marker = self.config.continuation_marker.as_deref();
+        let mut overhead = wrap.0.chars().count() + wrap.1.chars().count();
+        if self.config.bypass_duplicates {
+            overhead += DUPLICATE_BYPASS.chars().count();
+        }
+        // the wrappings are ours and short
+        debug_assert!(overhead < MAX_MESSAGE_LENGTH);
+        let max_length = MAX_MESSAGE_LENGTH - overhead;
+        for piece in split_message(text_param(text)?, max_length, marker) {
+            let text = self.vary_duplicate(&channel, wrap, piece);
+            let mut msg = Message::new(Command::Privmsg, &[&channel], Some(&text));
+            if let Some(id) = parent_msg_id {
+                msg = msg.with_tag("reply-parent-msg-id", id);
+            }
+            self.send(&msg)?;
         }
         Ok(())
     }
+
+    // the bypass goes inside the wrapping, so an action stays an action
+    fn vary_duplicate(&self, channel: &str, (prefix, suffix): (&str, &str), piece: String) -> String {
+        let text = format!("{}{}{}", prefix, piece, suffix);
+        if !self.config.bypass_duplicates {
+            return text;
+        }
+        let now = Instant::now();
+        let mut last_sent = self.last_sent.lock().unwrap();
+        let text = match last_sent.get(channel) {
+            Some((last, at)) if *last == text && now.duration_since(*at) < DUPLICATE_WINDOW => {
+                format!("{}{}{}{}", prefix, piece, DUPLICATE_BYPASS, suffix)
+            }
+            _ => text,
+        };
+        last_sent.insert(channel.into(), (text.clone(), now));
+        text
+    }
+
+    /// The settings of a channel we are in, `None` before its first ROOMSTATE.
+    pub fn room_state(&self, channel: &str) -> Option<RoomState> {
+        self.shared.lock().unwrap().room_states.get(&normalize_channel(channel)).cloned()
+    }
+
+    /// Our identity across channels, `None` before the server sent GLOBALUSERSTATE.
+    pub fn global_user_state(&self) -> Option<UserState> {
+        self.shared.lock().unwrap().global_user_state.clone()
+    }
+
+    /// Our badges and roles in a channel, `None` before the first USERSTATE there.
+    pub fn user_state(&self, channel: &str) -> Option<UserState> {
+        self.shared.lock().unwrap().user_states.get(&normalize_channel(channel)).cloned()
+    }
+
+    /// The logins in a channel we are in, sorted. Twitch batches JOIN/PART and sends none
+    /// for regular users once a channel has more than 1000 chatters, so this is an approximation.
+    pub fn chatters(&self, channel: &str) -> Option<Vec<String>> {
+        self.shared.lock().unwrap().membership.chatters(&normalize_channel(channel))
+    }
+
+    /// Lines sent (and channels to re-join) that are still waiting for the rate limiter or the connection.
+    pub fn queue_len(&self) -> usize {
+        self.shared.lock().unwrap().queued
+    }
+
+    /// The initial channels plus those the server confirmed we joined, minus those we parted.
+    pub fn channels(&self) -> Vec<String> {
+        self.shared.lock().unwrap().channels.clone()
+    }
+
+    /// Capabilities the server acknowledged with `CAP ACK` during the last login.
+    pub fn capabilities(&self) -> Vec<String> {
+        self.shared.lock().unwrap().capabilities.clone()
+    }
+}
+
+// a CR or LF would end the line early and smuggle in another command
+fn check_line_breaks(value: &str) -> Result<(), ClientError> {
+    if value.contains(['\r', '\n']) {
+        return Err(ClientError::InvalidInput(format!("line break in {:?}", value)));
+    }
+    Ok(())
+}
+
+fn channel_param(channel: &str) -> Result<String, ClientError> {
+    // before normalizing, which trims them away
+    check_line_breaks(channel)?;
+    let channel = normalize_channel(channel);
+    let name = &channel[1..];
+    if name.is_empty() || name.contains([' ', ',', '\0']) {
+        return Err(ClientError::InvalidInput(format!("not a valid channel: {:?}", channel)));
+    }
+    Ok(channel)
+}
+
+fn text_param(text: &str) -> Result<&str, ClientError> {
+    check_line_breaks(text)?;
+    if text.trim().is_empty() {
+        return Err(ClientError::InvalidInput("empty message".into()));
+    }
+    Ok(text)
 }
 
 pub struct ClientIterator<'a> {
-    receiver: &'a Receiver<Message>,
+    receiver: &'a Receiver<Event>,
 }
 
 impl<'a> Iterator for ClientIterator<'a> {
-    type Item = Message;
+    type Item = Event;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Ok(msg) = self.receiver.recv() {
-            Some(msg)
-        } else {
-            None
-        }
+        self.receiver.recv().ok()
     }
 }
 
 impl Client {
-    pub fn iter(&self) -> ClientIterator {
+    pub fn iter(&self) -> ClientIterator<'_> {
         ClientIterator {
             receiver: self.receiver.as_ref().unwrap(),
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use crate::irc::protocol::{Command, Message};
+    use crate::irc::roomstate::RoomMode;
+
+    // a stand-in for tmi.twitch.tv that the tests drive line by line.
+    struct MockServer {
+        listener: TcpListener,
+    }
+
+    struct MockConnection {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl MockServer {
+        fn new() -> MockServer {
+            MockServer { listener: TcpListener::bind("127.0.0.1:0").unwrap() }
+        }
+
+        fn builder(&self) -> ClientBuilder {
+            let port = self.listener.local_addr().unwrap().port();
+            ClientBuilder::new("oauth:secret", "bot")
+                .address("127.0.0.1", port)
+                .connect_timeout(Some(Duration::from_secs(5)))
+                .reconnect_policy(ReconnectPolicy {
+                    max_attempts: Some(3),
+                    base_delay: Duration::from_millis(10),
+                    max_delay: Duration::from_millis(10),
+                    jitter: 0.0,
+                })
+        }
+
+        fn accept(&self) -> MockConnection {
+            let (stream, _) = self.listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            MockConnection {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+            }
+        }
+
+        // runs `connect` on another thread, since it blocks until `script` welcomed the client
+        fn connect<F>(&self, mut client: Client, script: F) -> (Client, Result<(), ClientError>, MockConnection)
+        where
+            F: FnOnce(&mut MockConnection),
+        {
+            let handle = std::thread::spawn(move || {
+                let result = client.connect();
+                (client, result)
+            });
+            let mut conn = self.accept();
+            script(&mut conn);
+            let (client, result) = handle.join().unwrap();
+            (client, result, conn)
+        }
+
+        // a logged in client, with the events of the login already consumed
+        fn connected(&self, client: Client) -> (Client, MockConnection) {
+            let (client, result, conn) = self.connect(client, |conn| {
+                conn.login();
+            });
+            result.unwrap();
+            assert!(matches!(next_event(&client), Event::Connected));
+            assert_eq!(next_message(&client).command, Command::Cap);
+            assert_eq!(next_message(&client).command, Command::Ready);
+            (client, conn)
+        }
+    }
+
+    impl MockConnection {
+        fn read_line(&mut self) -> String {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            line.trim_end().into()
+        }
+
+        fn send(&mut self, line: &str) {
+            self.writer.write_all(format!("{}\r\n", line).as_bytes()).unwrap();
+        }
+
+        // reads the CAP/PASS/NICK lines and welcomes the client
+        fn login(&mut self) -> Vec<String> {
+            let lines = vec![self.read_line(), self.read_line(), self.read_line()];
+            self.send(":tmi.twitch.tv CAP * ACK :twitch.tv/membership twitch.tv/tags twitch.tv/commands");
+            self.send(":tmi.twitch.tv 001 bot :Welcome, GLHF!");
+            lines
+        }
+    }
+
+    fn next_event(client: &Client) -> Event {
+        client.receiver.as_ref().unwrap().recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    fn next_message(client: &Client) -> Message {
+        loop {
+            if let Event::Message(msg) = next_event(client) {
+                return msg;
+            }
+        }
+    }
+
+    #[test]
+    fn test_builder_handshake() {
+        let server = MockServer::new();
+        let client = server.builder()
+            .capabilities(&["twitch.tv/tags"])
+            .channels(&["Foo", "#bar"])
+            .build();
+        let (client, result, mut conn) = server.connect(client, |conn| {
+            assert_eq!(conn.read_line(), "CAP REQ :twitch.tv/tags");
+            assert_eq!(conn.read_line(), "PASS oauth:secret");
+            assert_eq!(conn.read_line(), "NICK bot");
+            conn.send(":tmi.twitch.tv CAP * ACK :twitch.tv/tags");
+            conn.send(":tmi.twitch.tv 001 bot :Welcome, GLHF!");
+        });
+        result.unwrap();
+        assert_eq!(client.capabilities(), vec!["twitch.tv/tags"]);
+
+        assert!(matches!(next_event(&client), Event::Connected));
+        assert_eq!(next_message(&client).command, Command::Cap);
+        assert_eq!(next_message(&client).command, Command::Ready);
+        assert_eq!(conn.read_line(), "JOIN #foo");
+        assert_eq!(conn.read_line(), "JOIN #bar");
+    }
+
+    #[test]
+    fn test_lines_after_welcome() {
+        let server = MockServer::new();
+        let (client, result, mut conn) = server.connect(server.builder().capabilities(&[]).build(), |conn| {
+            conn.read_line();
+            conn.read_line();
+            // 001 and the first line after it arrive in the same chunk
+            conn.writer.write_all(b":tmi.twitch.tv 001 bot :Welcome\r\n:tmi.twitch.tv 002 bot :Your host").unwrap();
+        });
+        result.unwrap();
+        conn.writer.write_all(b" is tmi.twitch.tv\r\n").unwrap();
+        assert_eq!(next_message(&client).command, Command::Ready);
+        assert_eq!(next_message(&client).params, vec!["bot", "Your host is tmi.twitch.tv"]);
+    }
+
+    #[test]
+    fn test_complete_lines_with_welcome() {
+        let server = MockServer::new();
+        let (client, result, _conn) = server.connect(server.builder().capabilities(&[]).build(), |conn| {
+            conn.read_line();
+            conn.read_line();
+            // nothing else is sent, so GLOBALUSERSTATE can't wait for the next read
+            conn.writer.write_all(b":tmi.twitch.tv 001 bot :Welcome\r\n@display-name=Bot;user-id=42 :tmi.twitch.tv GLOBALUSERSTATE\r\n").unwrap();
+        });
+        result.unwrap();
+        assert_eq!(next_message(&client).command, Command::Ready);
+        assert_eq!(next_message(&client).command, Command::GlobalUserState);
+        assert_eq!(client.global_user_state().unwrap().user_id.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn test_send_after_connect() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().build());
+        client.send_line("PRIVMSG #foo :hello").unwrap();
+        assert_eq!(conn.read_line(), "PRIVMSG #foo :hello");
+    }
+
+    // the connection thread writes asynchronously, so wait for the queue to settle
+    fn wait_for_queue(client: &Client, len: usize) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while client.queue_len() != len {
+            assert!(std::time::Instant::now() < deadline, "queue has {} lines", client.queue_len());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let server = MockServer::new();
+        let limits = RateLimits {
+            messages: 2,
+            elevated_messages: 4,
+            message_window: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let (client, mut conn) = server.connected(server.builder().rate_limits(Some(limits)).build());
+        for i in 0..3 {
+            client.send_line(&format!("PRIVMSG #foo :{}", i)).unwrap();
+        }
+        client.send_line("PART #foo").unwrap();
+        assert_eq!(conn.read_line(), "PRIVMSG #foo :0");
+        assert_eq!(conn.read_line(), "PRIVMSG #foo :1");
+        // the third message waits, and the PART behind it too
+        wait_for_queue(&client, 2);
+
+        // moderators get the higher limit
+        conn.send("@badge-info=;badges=moderator/1 :tmi.twitch.tv USERSTATE #foo");
+        assert_eq!(conn.read_line(), "PRIVMSG #foo :2");
+        assert_eq!(conn.read_line(), "PART #foo");
+        wait_for_queue(&client, 0);
+    }
+
+    #[test]
+    fn test_user_state() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().build());
+        conn.send("@badge-info=;badges=;color=#FF0000;display-name=Bot;emote-sets=0;user-id=42;user-type= :tmi.twitch.tv GLOBALUSERSTATE");
+        conn.send("@badge-info=;badges=vip/1;color=#FF0000;display-name=Bot;emote-sets=0;mod=0;subscriber=0;vip=1 :tmi.twitch.tv USERSTATE #foo");
+        assert_eq!(next_message(&client).command, Command::GlobalUserState);
+        assert_eq!(next_message(&client).command, Command::UserState);
+
+        let global = client.global_user_state().unwrap();
+        assert_eq!(global.user_id.as_deref(), Some("42"));
+        assert_eq!(global.display_name.as_deref(), Some("Bot"));
+        assert!(client.user_state("#foo").unwrap().vip);
+        assert_eq!(client.user_state("#bar"), None);
+
+        conn.send(":bot!bot@bot.tmi.twitch.tv PART #foo");
+        assert_eq!(next_message(&client).command, Command::Part);
+        assert_eq!(client.user_state("#foo"), None);
+    }
+
+    #[test]
+    fn test_chatters() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().build());
+        conn.send(":bot!bot@bot.tmi.twitch.tv JOIN #foo");
+        conn.send(":bot.tmi.twitch.tv 353 bot = #foo :bot alice");
+        conn.send(":bot.tmi.twitch.tv 366 bot #foo :End of /NAMES list");
+        conn.send(":bob!bob@bob.tmi.twitch.tv JOIN #foo");
+        assert_eq!(next_message(&client).command, Command::Join);
+        assert_eq!(next_message(&client).command, Command::Names);
+        assert_eq!(next_message(&client).command, Command::EndOfNames);
+        assert_eq!(next_message(&client).command, Command::Join);
+        assert!(matches!(next_event(&client), Event::UserJoined(channel, user) if channel == "#foo" && user == "bob"));
+        assert_eq!(client.chatters("foo").unwrap(), vec!["alice", "bob", "bot"]);
+    }
+
+    #[test]
+    fn test_rate_limit_joins() {
+        let server = MockServer::new();
+        let limits = RateLimits {
+            joins: 2,
+            join_window: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let client = server.builder()
+            .channels(&["a", "b", "c"])
+            .rate_limits(Some(limits))
+            .build();
+        let (client, mut conn) = server.connected(client);
+        assert_eq!(conn.read_line(), "JOIN #a");
+        assert_eq!(conn.read_line(), "JOIN #b");
+        wait_for_queue(&client, 1);
+
+        // a JOIN with more channels than the limit is split up
+        client.send_line("JOIN #d,#e,#f").unwrap();
+        wait_for_queue(&client, 3);
+    }
+
+    #[test]
+    fn test_send_helpers() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().build());
+        client.join("Foo").unwrap();
+        assert_eq!(conn.read_line(), "JOIN #foo");
+        client.privmsg("#foo", "hello").unwrap();
+        assert_eq!(conn.read_line(), "PRIVMSG #foo :hello");
+        client.reply("b34ccfc7-4977-403a-8a94-33c6bac34fb8", "foo", "hi there").unwrap();
+        assert_eq!(conn.read_line(), "@reply-parent-msg-id=b34ccfc7-4977-403a-8a94-33c6bac34fb8 PRIVMSG #foo :hi there");
+        client.action("foo", "waves").unwrap();
+        assert_eq!(conn.read_line(), "PRIVMSG #foo :\x01ACTION waves\x01");
+        client.part("#foo").unwrap();
+        assert_eq!(conn.read_line(), "PART #foo");
+        client.send_line("PING :tmi.twitch.tv\r\n").unwrap();
+        assert_eq!(conn.read_line(), "PING :tmi.twitch.tv");
+    }
+
+    #[test]
+    fn test_privmsg_split() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().continuation_marker(Some("…")).build());
+        let text = format!("{} {}", "ä".repeat(300), "ö".repeat(300));
+        client.action("#foo", &text).unwrap();
+        assert_eq!(conn.read_line(), format!("PRIVMSG #foo :\x01ACTION {} …\x01", "ä".repeat(300)));
+        assert_eq!(conn.read_line(), format!("PRIVMSG #foo :\x01ACTION {}\x01", "ö".repeat(300)));
+    }
+
+    #[test]
+    fn test_bypass_duplicates() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().bypass_duplicates(true).build());
+        client.privmsg("#foo", "status: ok").unwrap();
+        client.privmsg("#foo", "status: ok").unwrap();
+        client.privmsg("#foo", "status: ok").unwrap();
+        client.privmsg("#bar", "status: ok").unwrap();
+        client.action("#bar", "waves").unwrap();
+        client.action("#bar", "waves").unwrap();
+        assert_eq!(conn.read_line(), "PRIVMSG #foo :status: ok");
+        assert_eq!(conn.read_line(), "PRIVMSG #foo :status: ok \u{E0000}");
+        assert_eq!(conn.read_line(), "PRIVMSG #foo :status: ok");
+        assert_eq!(conn.read_line(), "PRIVMSG #bar :status: ok");
+        assert_eq!(conn.read_line(), "PRIVMSG #bar :\x01ACTION waves\x01");
+        assert_eq!(conn.read_line(), "PRIVMSG #bar :\x01ACTION waves \u{E0000}\x01");
+    }
+
+    #[test]
+    fn test_duplicate_notice() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().build());
+        conn.send("@msg-id=msg_duplicate :tmi.twitch.tv NOTICE #foo :Your message was not sent because it is identical to the previous one you sent, less than 30 seconds ago.");
+        assert_eq!(next_message(&client).command, Command::Notice);
+        assert!(matches!(next_event(&client), Event::Error(ClientError::DuplicateMessage(channel)) if channel == "#foo"));
+    }
+
+    #[test]
+    fn test_room_state() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().build());
+        assert_eq!(client.room_state("foo"), None);
+        conn.send("@emote-only=0;followers-only=-1;r9k=0;room-id=1;slow=0;subs-only=0 :tmi.twitch.tv ROOMSTATE #foo");
+        assert_eq!(next_message(&client).command, Command::RoomState);
+        conn.send("@room-id=1;slow=10 :tmi.twitch.tv ROOMSTATE #foo");
+        assert_eq!(next_message(&client).command, Command::RoomState);
+        match next_event(&client) {
+            Event::RoomModeChanged(channel, mode) => {
+                assert_eq!(channel, "#foo");
+                assert_eq!(mode, RoomMode::Slow(Some(10)));
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+        assert_eq!(client.room_state("Foo").unwrap().slow, Some(10));
+
+        conn.send(":bot!bot@bot.tmi.twitch.tv PART #foo");
+        assert_eq!(next_message(&client).command, Command::Part);
+        assert_eq!(client.room_state("foo"), None);
+    }
+
+    #[test]
+    fn test_send_helpers_invalid_input() {
+        let server = MockServer::new();
+        let (client, _conn) = server.connected(server.builder().build());
+        assert!(matches!(client.privmsg("#foo", "hi\r\nJOIN #bar"), Err(ClientError::InvalidInput(_))));
+        assert!(matches!(client.privmsg("#foo\n", "hi"), Err(ClientError::InvalidInput(_))));
+        assert!(matches!(client.privmsg("#foo", " "), Err(ClientError::InvalidInput(_))));
+        assert!(matches!(client.join("#"), Err(ClientError::InvalidInput(_))));
+        assert!(matches!(client.join("#foo bar"), Err(ClientError::InvalidInput(_))));
+        assert!(matches!(client.action("#foo", "hi\x01"), Err(ClientError::InvalidInput(_))));
+        assert!(matches!(client.send_line("PRIVMSG #foo :a\rb"), Err(ClientError::InvalidInput(_))));
+        assert_eq!(client.queue_len(), 0);
+    }
+
+    #[test]
+    fn test_reconnect_command() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().build());
+        client.send_line("JOIN #foo").unwrap();
+        assert_eq!(conn.read_line(), "JOIN #foo");
+        conn.send(":bot!bot@bot.tmi.twitch.tv JOIN #foo");
+        assert_eq!(next_message(&client).command, Command::Join);
+        assert_eq!(client.channels(), vec!["#foo"]);
+
+        conn.send(":tmi.twitch.tv RECONNECT");
+        assert_eq!(next_message(&client).command, Command::Reconnect);
+
+        let mut conn = server.accept();
+        conn.login();
+        assert!(matches!(next_event(&client), Event::Connected));
+        assert_eq!(conn.read_line(), "JOIN #foo");
+
+        // the second login isn't forwarded, the next message is the one after it.
+        conn.send(":nick!nick@nick.tmi.twitch.tv PRIVMSG #foo :still here");
+        assert_eq!(next_message(&client).params, vec!["#foo", "still here"]);
+    }
+
+    #[test]
+    fn test_reconnect_rejoins_channels() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().channels(&["foo"]).build());
+        assert_eq!(conn.read_line(), "JOIN #foo");
+        conn.send(":bot!bot@bot.tmi.twitch.tv JOIN #foo");
+        client.join("bar").unwrap();
+        assert_eq!(conn.read_line(), "JOIN #bar");
+        conn.send(":bot!bot@bot.tmi.twitch.tv JOIN #bar");
+        assert_eq!(next_message(&client).params, vec!["#foo"]);
+        assert_eq!(next_message(&client).params, vec!["#bar"]);
+
+        conn.send(":tmi.twitch.tv RECONNECT");
+        assert_eq!(next_message(&client).command, Command::Reconnect);
+
+        // the new connection goes through the whole handshake and joins both channels again
+        let mut conn = server.accept();
+        assert_eq!(conn.login(), vec![
+            "CAP REQ :twitch.tv/membership twitch.tv/tags twitch.tv/commands",
+            "PASS oauth:secret",
+            "NICK bot",
+        ]);
+        assert!(matches!(next_event(&client), Event::Connected));
+        assert_eq!(conn.read_line(), "JOIN #foo");
+        assert_eq!(conn.read_line(), "JOIN #bar");
+
+        // no Disconnected in between, the iterator just goes on
+        conn.send(":nick!nick@nick.tmi.twitch.tv PRIVMSG #foo :one");
+        conn.send(":nick!nick@nick.tmi.twitch.tv PRIVMSG #bar :two");
+        let texts = client.iter().take(2).map(|event| match event {
+            Event::Message(msg) => msg.params[1].clone(),
+            e => panic!("unexpected event {:?}", e),
+        }).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["one", "two"]);
+    }
+
+    #[test]
+    fn test_reconnect_after_disconnect() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().channels(&["foo"]).build());
+        assert_eq!(conn.read_line(), "JOIN #foo");
+        drop(conn);
+
+        assert!(matches!(next_event(&client), Event::Error(ClientError::Io(_))));
+        assert!(matches!(next_event(&client), Event::Disconnected));
+        assert!(matches!(next_event(&client), Event::Reconnecting(1)));
+
+        let mut conn = server.accept();
+        conn.login();
+        assert!(matches!(next_event(&client), Event::Connected));
+        assert_eq!(conn.read_line(), "JOIN #foo");
+    }
+
+    #[test]
+    fn test_reconnect_gives_up() {
+        let server = MockServer::new();
+        let (client, conn) = server.connected(server.builder().build());
+        drop(conn);
+        drop(server);
+
+        assert!(matches!(next_event(&client), Event::Error(ClientError::Io(_))));
+        assert!(matches!(next_event(&client), Event::Disconnected));
+        for attempt in 1..=3 {
+            assert!(matches!(next_event(&client), Event::Reconnecting(n) if n == attempt));
+            assert!(matches!(next_event(&client), Event::Error(ClientError::Io(_))));
+        }
+        // the iterator ends once the policy runs out
+        assert_eq!(client.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_authentication_failure() {
+        let server = MockServer::new();
+        let (_, result, _) = server.connect(server.builder().build(), |conn| {
+            conn.read_line();
+            conn.read_line();
+            conn.read_line();
+            conn.send(":tmi.twitch.tv NOTICE * :Login authentication failed");
+        });
+        match result {
+            Err(ClientError::Authentication(notice)) => assert_eq!(notice, "Login authentication failed"),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_capability_rejected() {
+        let server = MockServer::new();
+        let (_, result, _) = server.connect(server.builder().build(), |conn| {
+            conn.read_line();
+            conn.send(":tmi.twitch.tv CAP * NAK :twitch.tv/membership twitch.tv/tags twitch.tv/commands");
+        });
+        match result {
+            Err(ClientError::CapabilityRejected(caps)) => assert_eq!(caps.len(), 3),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_login_timeout() {
+        let server = MockServer::new();
+        let client = server.builder().connect_timeout(Some(Duration::from_millis(200))).build();
+        let (_, result, _) = server.connect(client, |conn| {
+            conn.read_line();
+        });
+        match result {
+            Err(ClientError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_authentication_failure_on_reconnect() {
+        let server = MockServer::new();
+        let (client, mut conn) = server.connected(server.builder().build());
+        conn.send(":tmi.twitch.tv RECONNECT");
+
+        let mut conn = server.accept();
+        conn.read_line();
+        conn.read_line();
+        conn.read_line();
+        conn.send(":tmi.twitch.tv NOTICE * :Login authentication failed");
+
+        let events = client.iter().collect::<Vec<_>>();
+        assert!(matches!(events.last(), Some(Event::Error(ClientError::Authentication(_)))));
+    }
+
+    #[test]
+    fn test_anonymous() {
+        let server = MockServer::new();
+        let port = server.listener.local_addr().unwrap().port();
+        let client = ClientBuilder::anonymous().address("127.0.0.1", port).build();
+        assert!(client.is_anonymous());
+        let nickname = client.nickname().to_string();
+        assert!(nickname.starts_with("justinfan"));
+        assert!(nickname["justinfan".len()..].parse::<u32>().is_ok());
+
+        let (client, result, mut conn) = server.connect(client, |conn| {
+            conn.read_line();
+            assert_eq!(conn.read_line(), format!("NICK {}", nickname));
+            conn.send(":tmi.twitch.tv 001 justinfan :Welcome, GLHF!");
+        });
+        result.unwrap();
+
+        assert!(matches!(client.send_line("PRIVMSG #foo :hello"), Err(ClientError::ReadOnly)));
+        assert!(matches!(client.send_line("@reply-parent-msg-id=1 PRIVMSG #foo :hello"), Err(ClientError::ReadOnly)));
+        assert!(matches!(client.privmsg("#foo", "hello"), Err(ClientError::ReadOnly)));
+        assert!(matches!(client.action("#foo", "hello"), Err(ClientError::ReadOnly)));
+        client.send_line("JOIN #foo").unwrap();
+        assert_eq!(conn.read_line(), "JOIN #foo");
+    }
+
+    #[test]
+    fn test_connect_refused() {
+        let server = MockServer::new();
+        let mut client = server.builder().build();
+        drop(server);
+        assert!(matches!(client.connect(), Err(ClientError::Io(_))));
+        assert!(matches!(client.send_line("PING"), Err(ClientError::NotConnected)));
+    }
+}
@@ -0,0 +1,484 @@
+use std::{io, thread};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+use crate::irc::client::{ClientConfig, ClientError, SharedState};
+use crate::irc::event::Event;
+use crate::irc::notice::NoticeKind;
+use crate::irc::protocol::{Command, Message, parse_line};
+use crate::irc::ratelimit::{Cost, RateLimiter};
+use crate::irc::roomstate::{RoomMode, RoomState};
+use crate::irc::userstate::UserState;
+use crate::irc::transport::Transport;
+use crate::irc::utils::random_unit;
+
+// how long a read blocks before queued outgoing lines get a chance to be written.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// what twitch answers to a bad or malformed token, right before closing the connection.
+const LOGIN_FAILURES: [&str; 3] = ["Login authentication failed", "Improperly formatted auth", "Login unsuccessful"];
+
+fn open_stream(config: &ClientConfig) -> io::Result<Transport> {
+    let mut stream = Transport::connect(&config.host, config.port(), config.tls.as_ref(), config.connect_timeout)?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+
+    if !config.capabilities.is_empty() {
+        write_line(&mut stream, &format!("CAP REQ :{}", config.capabilities.join(" ")))?;
+    }
+    if let Some(token) = &config.token {
+        write_line(&mut stream, &format!("PASS {}", token.value))?;
+    }
+    write_line(&mut stream, &format!("NICK {}", config.nickname))?;
+    Ok(stream)
+}
+
+fn write_line(stream: &mut impl Write, line: &str) -> io::Result<()> {
+    stream.write_all(format!("{}\r\n", line).as_bytes())
+}
+
+fn is_login_failure(msg: &Message) -> bool {
+    msg.command == Command::Notice
+        && msg.params.first().is_some_and(|target| target == "*")
+        && msg.params.last().is_some_and(|text| LOGIN_FAILURES.contains(&text.as_str()))
+}
+
+// `CAP * ACK :a b c` -> ("ACK", [a, b, c])
+fn cap_reply(msg: &Message) -> Option<(&str, Vec<String>)> {
+    if msg.command != Command::Cap || msg.params.len() < 3 {
+        return None;
+    }
+    let caps = msg.params[2].split_whitespace().map(|c| c.to_string()).collect();
+    Some((msg.params[1].as_str(), caps))
+}
+
+/// Splits the received bytes into lines.
+#[derive(Default)]
+struct LineBuffer {
+    buf: Vec<u8>,
+}
+
+impl LineBuffer {
+    fn extend(&mut self, chunk: &[u8]) {
+        self.buf.extend(chunk);
+    }
+
+    // the line without its line ending, or the raw bytes if they aren't UTF-8
+    fn next_line(&mut self) -> Option<Result<String, Vec<u8>>> {
+        let pos = self.buf.iter().position(|c| *c == b'\n')?;
+        let line_vec = self.buf.drain(..=pos).collect::<Vec<u8>>();
+        Some(match String::from_utf8(line_vec) {
+            Ok(line) => Ok(line.trim_end_matches(['\r', '\n']).into()),
+            Err(e) => Err(e.into_bytes()),
+        })
+    }
+}
+
+/// A connection the server has accepted our login on.
+pub(crate) struct Session {
+    stream: Transport,
+    lines: LineBuffer,
+    capabilities: Vec<String>,
+    // everything up to and including 001
+    received: Vec<Message>,
+    last_read: Instant,
+}
+
+impl Session {
+    /// Connects, logs in and waits for 001. `connect_timeout` applies to both steps.
+    pub(crate) fn establish(config: &ClientConfig) -> Result<Session, ClientError> {
+        let mut stream = open_stream(config)?;
+        let deadline = config.connect_timeout.map(|timeout| Instant::now() + timeout);
+        let mut lines = LineBuffer::default();
+        let mut capabilities = vec![];
+        let mut received = vec![];
+
+        loop {
+            if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                return Err(io::Error::new(ErrorKind::TimedOut, "server didn't accept the login in time").into());
+            }
+
+            let mut buf = [0u8; 8096];
+            match stream.read(&mut buf) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "server closed the connection").into()),
+                Ok(size) => lines.extend(&buf[..size]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            while let Some(line) = lines.next_line() {
+                // nothing before 001 is worth failing the login over
+                let Some(msg) = line.ok().and_then(|line| parse_line(&line).ok()) else {
+                    continue;
+                };
+                if msg.command == Command::Ping {
+                    write_line(&mut stream, &format!("{}", msg.with_command(Command::Pong)))?;
+                    continue;
+                }
+                if is_login_failure(&msg) {
+                    return Err(ClientError::Authentication(msg.params.last().cloned().unwrap_or_default()));
+                }
+                match cap_reply(&msg) {
+                    Some(("ACK", caps)) => capabilities.extend(caps),
+                    Some(("NAK", caps)) => return Err(ClientError::CapabilityRejected(caps)),
+                    _ => (),
+                }
+
+                let ready = msg.command == Command::Ready;
+                received.push(msg);
+                if ready {
+                    return Ok(Session {
+                        stream,
+                        lines,
+                        capabilities,
+                        received,
+                        last_read: Instant::now(),
+                    });
+                }
+            }
+        }
+    }
+
+    pub(crate) fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+
+    fn write(&mut self, line: &str) -> Result<(), Exit> {
+        write_line(&mut self.stream, line).map_err(|e| Exit::ConnectionLost(e.into()))
+    }
+}
+
+enum Exit {
+    // the client (or its receiver) was dropped, or retrying makes no sense
+    Stop,
+    ConnectionLost(ClientError),
+}
+
+/// Runs on its own thread, forwards parsed messages to the client and writes the lines the
+/// client queued, in order and within the rate limits. Replaces the session when it is lost
+/// or the server asks for a reconnect.
+pub(crate) struct Connection {
+    config: ClientConfig,
+    sender: Sender<Event>,
+    outgoing: Receiver<String>,
+    shared: Arc<Mutex<SharedState>>,
+    // lines waiting for the rate limiter, kept across sessions
+    pending: VecDeque<String>,
+    limiter: Option<RateLimiter>,
+    // the messages of the first login go to the consumer, those after a reconnect are swallowed.
+    welcomed: bool,
+    // failed attempts since the last successful login
+    attempt: u32,
+}
+
+impl Connection {
+    pub(crate) fn new(
+        config: ClientConfig,
+        sender: Sender<Event>,
+        outgoing: Receiver<String>,
+        shared: Arc<Mutex<SharedState>>,
+    ) -> Connection {
+        let limiter = config.rate_limits.as_ref().map(RateLimiter::new);
+        Connection {
+            config,
+            sender,
+            outgoing,
+            shared,
+            pending: VecDeque::new(),
+            limiter,
+            welcomed: false,
+            attempt: 0,
+        }
+    }
+
+    pub(crate) fn run(mut self, mut session: Session) {
+        loop {
+            let error = match self.serve(&mut session) {
+                Exit::Stop => return,
+                Exit::ConnectionLost(error) => error,
+            };
+            let recovered = self.emit(Event::Error(error))
+                .and_then(|_| self.emit(Event::Disconnected))
+                .and_then(|_| self.reconnect());
+            match recovered {
+                Ok(new_session) => session = new_session,
+                Err(_) => return,
+            }
+        }
+    }
+
+    fn serve(&mut self, session: &mut Session) -> Exit {
+        if let Err(exit) = self.welcome(session) {
+            return exit;
+        }
+        loop {
+            if let Err(exit) = self.poll(session) {
+                return exit;
+            }
+        }
+    }
+
+    fn welcome(&mut self, session: &mut Session) -> Result<(), Exit> {
+        self.attempt = 0;
+        self.shared.lock().unwrap().capabilities = session.capabilities.clone();
+        self.emit(Event::Connected)?;
+        for msg in session.received.drain(..) {
+            if !self.welcomed {
+                self.emit(Event::Message(msg))?;
+            }
+        }
+        self.welcomed = true;
+        self.rejoin();
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Result<Session, Exit> {
+        loop {
+            self.attempt += 1;
+            if !self.config.reconnect_policy.allows(self.attempt) {
+                return Err(Exit::Stop);
+            }
+            self.emit(Event::Reconnecting(self.attempt))?;
+            thread::sleep(self.config.reconnect_policy.delay(self.attempt, random_unit()));
+            match self.establish() {
+                Ok(session) => return Ok(session),
+                Err(Exit::ConnectionLost(e)) => self.emit(Event::Error(e))?,
+                Err(Exit::Stop) => return Err(Exit::Stop),
+            }
+        }
+    }
+
+    fn establish(&mut self) -> Result<Session, Exit> {
+        match Session::establish(&self.config) {
+            Ok(session) => Ok(session),
+            // retrying with the same credentials or capabilities won't help
+            Err(e @ (ClientError::Authentication(_) | ClientError::CapabilityRejected(_))) => {
+                self.emit(Event::Error(e))?;
+                Err(Exit::Stop)
+            }
+            Err(e) => Err(Exit::ConnectionLost(e)),
+        }
+    }
+
+    fn poll(&mut self, session: &mut Session) -> Result<(), Exit> {
+        // what arrived together with 001 is already buffered, the server may not send more for minutes
+        self.handle_lines(session)?;
+        self.flush_outgoing(session)?;
+
+        let mut buf = [0u8; 8096];
+        let size = match session.stream.read(&mut buf) {
+            Ok(0) => {
+                let e = io::Error::new(ErrorKind::UnexpectedEof, "server closed the connection");
+                return Err(Exit::ConnectionLost(e.into()));
+            }
+            Ok(size) => size,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if self.config.idle_timeout.is_some_and(|timeout| session.last_read.elapsed() > timeout) {
+                    let e = io::Error::new(ErrorKind::TimedOut, "no data from the server");
+                    return Err(Exit::ConnectionLost(e.into()));
+                }
+                return Ok(());
+            }
+            Err(e) => return Err(Exit::ConnectionLost(e.into())),
+        };
+        session.lines.extend(&buf[..size]);
+        session.last_read = Instant::now();
+        self.handle_lines(session)
+    }
+
+    fn handle_lines(&mut self, session: &mut Session) -> Result<(), Exit> {
+        while let Some(line) = session.lines.next_line() {
+            let line = match line {
+                Ok(line) => line,
+                Err(bytes) => {
+                    self.emit(Event::Error(ClientError::InvalidUtf8(bytes)))?;
+                    continue;
+                }
+            };
+            match parse_line(&line) {
+                Ok(msg) => self.handle_message(session, msg)?,
+                Err(e) => self.emit(Event::Error(ClientError::Parse(e, line)))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_message(&mut self, session: &mut Session, msg: Message) -> Result<(), Exit> {
+        match msg.command {
+            Command::Ping => {
+                let pong = format!("{}", msg.with_command(Command::Pong));
+                return session.write(&pong);
+            }
+            Command::Join | Command::Part | Command::Names | Command::EndOfNames => {
+                self.track_membership(&msg);
+                let change = self.shared.lock().unwrap().membership.handle(&msg, &self.config.nickname);
+                self.emit(Event::Message(msg))?;
+                if let Some(event) = change {
+                    self.emit(event)?;
+                }
+                return Ok(());
+            }
+            Command::UserState | Command::GlobalUserState => self.track_user_state(&msg),
+            Command::RoomState => {
+                let changes = self.track_room_state(&msg);
+                let channel = msg.params.first().cloned().unwrap_or_default();
+                self.emit(Event::Message(msg))?;
+                for mode in changes {
+                    self.emit(Event::RoomModeChanged(channel.clone(), mode))?;
+                }
+                return Ok(());
+            }
+            Command::Notice if msg.notice_kind() == Some(NoticeKind::Duplicate) => {
+                let channel = msg.params.first().cloned().unwrap_or_default();
+                self.emit(Event::Message(msg))?;
+                return self.emit(Event::Error(ClientError::DuplicateMessage(channel)));
+            }
+            Command::Cap => {
+                let rejected = match cap_reply(&msg) {
+                    Some(("NAK", caps)) => Some(caps),
+                    _ => None,
+                };
+                self.emit(Event::Message(msg))?;
+                if let Some(caps) = rejected {
+                    return self.emit(Event::Error(ClientError::CapabilityRejected(caps)));
+                }
+                return Ok(());
+            }
+            Command::Reconnect => {
+                self.emit(Event::Message(msg))?;
+                // lines still buffered belong to the old connection and are dropped with it.
+                *session = self.establish()?;
+                return self.welcome(session);
+            }
+            _ => (),
+        }
+        self.emit(Event::Message(msg))
+    }
+
+    fn emit(&self, event: Event) -> Result<(), Exit> {
+        self.sender.send(event).map_err(|_| Exit::Stop)
+    }
+
+    fn flush_outgoing(&mut self, session: &mut Session) -> Result<(), Exit> {
+        loop {
+            match self.outgoing.try_recv() {
+                Ok(line) => self.queue(line),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Err(Exit::Stop),
+            }
+        }
+
+        // a line the limiter holds back blocks everything behind it
+        while let Some(line) = self.pending.front() {
+            let cost = self.cost(line);
+            if self.limiter.as_mut().is_some_and(|limiter| !limiter.try_acquire(&cost, Instant::now())) {
+                break;
+            }
+            // a line that failed to write stays queued for the next session
+            session.write(line)?;
+            self.pending.pop_front();
+            self.shared.lock().unwrap().queued -= 1;
+        }
+        Ok(())
+    }
+
+    // a JOIN with more channels than the limit allows at once goes out in batches
+    fn queue(&mut self, line: String) {
+        let max_joins = self.limiter.as_ref().map_or(usize::MAX, RateLimiter::max_joins);
+        let channels = match parse_line(&line) {
+            Ok(msg) if msg.command == Command::Join => msg.params.first().cloned().unwrap_or_default(),
+            _ => return self.pending.push_back(line),
+        };
+        let channels = channels.split(',').collect::<Vec<&str>>();
+        if channels.len() <= max_joins {
+            return self.pending.push_back(line);
+        }
+        let batches = channels.chunks(max_joins).map(|batch| format!("JOIN {}", batch.join(",")));
+        self.pending.extend(batches);
+        self.shared.lock().unwrap().queued += channels.len().div_ceil(max_joins) - 1;
+    }
+
+    fn cost(&self, line: &str) -> Cost {
+        let Ok(msg) = parse_line(line) else {
+            return Cost::Free;
+        };
+        match msg.command {
+            Command::Privmsg => Cost::Message {
+                elevated: msg.params.first().is_some_and(|channel| self.is_elevated(channel)),
+            },
+            Command::Join => Cost::Join(msg.params.first().map_or(1, |channels| channels.split(',').count())),
+            _ => Cost::Free,
+        }
+    }
+
+    fn is_elevated(&self, channel: &str) -> bool {
+        // the broadcaster only gets a broadcaster badge once USERSTATE arrived
+        channel.strip_prefix('#').is_some_and(|name| name.eq_ignore_ascii_case(&self.config.nickname))
+            || self.shared.lock().unwrap().user_states.get(channel).is_some_and(|state| state.has_elevated_rate_limit())
+    }
+
+    fn is_own_nick(&self, msg: &Message) -> bool {
+        msg.prefix.as_ref()
+            .and_then(|p| p.nick.as_ref())
+            .is_some_and(|nick| nick.eq_ignore_ascii_case(&self.config.nickname))
+    }
+
+    fn track_membership(&self, msg: &Message) {
+        if !matches!(msg.command, Command::Join | Command::Part) || !self.is_own_nick(msg) {
+            return;
+        }
+        let Some(channel) = msg.params.first() else {
+            return;
+        };
+        let mut shared = self.shared.lock().unwrap();
+        match msg.command {
+            Command::Join if !shared.channels.contains(channel) => shared.channels.push(channel.clone()),
+            Command::Part => {
+                shared.channels.retain(|c| c != channel);
+                shared.room_states.remove(channel);
+                shared.user_states.remove(channel);
+            }
+            _ => (),
+        }
+    }
+
+    // the first ROOMSTATE of a channel only sets the state, it doesn't change anything
+    fn track_room_state(&self, msg: &Message) -> Vec<RoomMode> {
+        let Some(channel) = msg.params.first() else {
+            return vec![];
+        };
+        let mut shared = self.shared.lock().unwrap();
+        match shared.room_states.get_mut(channel) {
+            Some(state) => state.update(msg),
+            None => {
+                let mut state = RoomState::default();
+                state.update(msg);
+                shared.room_states.insert(channel.clone(), state);
+                vec![]
+            }
+        }
+    }
+
+    fn track_user_state(&self, msg: &Message) {
+        let Some(state) = UserState::from_message(msg) else {
+            return;
+        };
+        let mut shared = self.shared.lock().unwrap();
+        match msg.params.first() {
+            Some(channel) => {
+                shared.user_states.insert(channel.clone(), state);
+            }
+            None => shared.global_user_state = Some(state),
+        }
+    }
+
+    // ahead of everything queued, but still subject to the JOIN limit
+    fn rejoin(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        for channel in shared.channels.iter().rev() {
+            self.pending.push_front(format!("JOIN {}", channel));
+        }
+        shared.queued += shared.channels.len();
+    }
+}
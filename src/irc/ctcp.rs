@@ -0,0 +1,81 @@
+use crate::irc::protocol::{Command, Message};
+
+pub const DELIMITER: char = '\x01';
+
+/// `\x01VERB args\x01` -> ("VERB", "args"). The closing delimiter is optional, some clients leave it out.
+pub fn parse(text: &str) -> Option<(&str, &str)> {
+    let inner = text.strip_prefix(DELIMITER)?;
+    let inner = inner.strip_suffix(DELIMITER).unwrap_or(inner);
+    let (verb, args) = inner.split_once(' ').unwrap_or((inner, ""));
+    if verb.is_empty() {
+        return None;
+    }
+    Some((verb, args))
+}
+
+/// The reverse of `parse`, `args` must not contain the delimiter.
+pub fn encode(verb: &str, args: &str) -> String {
+    if args.is_empty() {
+        format!("{}{}{}", DELIMITER, verb, DELIMITER)
+    } else {
+        let (prefix, suffix) = wrap(verb);
+        format!("{}{}{}", prefix, args, suffix)
+    }
+}
+
+/// What goes before and after the arguments of `verb`, for text that is only known piece by piece.
+pub fn wrap(verb: &str) -> (String, String) {
+    (format!("{}{} ", DELIMITER, verb), DELIMITER.to_string())
+}
+
+impl Message {
+    /// The CTCP verb and arguments of a PRIVMSG (a query) or NOTICE (a reply).
+    pub fn ctcp(&self) -> Option<(&str, &str)> {
+        if !matches!(self.command, Command::Privmsg | Command::Notice) {
+            return None;
+        }
+        parse(self.params.get(1)?)
+    }
+
+    /// A `/me` message.
+    pub fn is_action(&self) -> bool {
+        self.ctcp().is_some_and(|(verb, _)| verb == "ACTION")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::protocol::parse_line;
+
+    #[test]
+    fn test_action() {
+        let msg = parse_line(":nick!user@host PRIVMSG #channel :\x01ACTION waves hello\x01").unwrap();
+        assert!(msg.is_action());
+        assert_eq!(msg.ctcp(), Some(("ACTION", "waves hello")));
+        assert_eq!(msg.text(), Some("waves hello"));
+
+        let msg = parse_line(":nick!user@host PRIVMSG #channel :waves hello").unwrap();
+        assert!(!msg.is_action());
+        assert_eq!(msg.ctcp(), None);
+        assert_eq!(msg.text(), Some("waves hello"));
+    }
+
+    #[test]
+    fn test_other_verbs() {
+        let msg = parse_line(":nick!user@host PRIVMSG bot :\x01VERSION\x01").unwrap();
+        assert_eq!(msg.ctcp(), Some(("VERSION", "")));
+        assert!(!msg.is_action());
+        // the closing delimiter is optional
+        let msg = parse_line(":nick!user@host NOTICE bot :\x01PING 1234").unwrap();
+        assert_eq!(msg.ctcp(), Some(("PING", "1234")));
+        assert_eq!(parse("\x01\x01"), None);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("ACTION", "waves"), "\x01ACTION waves\x01");
+        assert_eq!(encode("VERSION", ""), "\x01VERSION\x01");
+        assert_eq!(parse(&encode("ACTION", "waves hello")), Some(("ACTION", "waves hello")));
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde_json::Value;
+use crate::irc::protocol::{EmoteInfo, Provider};
+
+type Result<T> = std::result::Result<T, LoadError>;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    // valid JSON, but not the emote format of the provider
+    Format(String),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "I/O error: {}", e),
+            LoadError::Json(e) => write!(f, "invalid JSON: {}", e),
+            LoadError::Format(reason) => write!(f, "unexpected emote format: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> Self {
+        LoadError::Json(e)
+    }
+}
+
+impl Provider {
+    // by the start of a file name in a cache directory: bttv*.json, ffz*.json, 7tv*.json
+    fn from_file_name(name: &str) -> Option<Provider> {
+        let name = name.to_lowercase();
+        if !name.ends_with(".json") {
+            return None;
+        }
+        if name.starts_with("bttv") {
+            Some(Provider::Bttv)
+        } else if name.starts_with("ffz") {
+            Some(Provider::Ffz)
+        } else if name.starts_with("7tv") {
+            Some(Provider::SevenTv)
+        } else {
+            None
+        }
+    }
+}
+
+/// Looks up third-party emotes, which are used like words in the message text.
+pub trait EmoteProvider {
+    fn emote(&self, word: &str) -> Option<EmoteInfo>;
+}
+
+/// Emotes by their exact name, loaded from the JSON the providers' APIs return.
+#[derive(Debug, Clone, Default)]
+pub struct EmoteSet {
+    emotes: HashMap<String, EmoteInfo>,
+}
+
+// ids are strings at BTTV and 7TV, numbers at FFZ
+fn id_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn array(value: Option<&Value>) -> impl Iterator<Item = &Value> {
+    value.and_then(|v| v.as_array()).into_iter().flatten()
+}
+
+// BTTV has no flag for them, these are its global overlay emotes
+const BTTV_ZERO_WIDTH: [&str; 8] = ["SoSnowy", "IceCold", "SantaHat", "TopHat", "ReinDeer", "CandyCane", "cvMask", "cvHazmat"];
+
+fn is_zero_width(provider: Provider, name: &str, emote: &Value) -> bool {
+    match provider {
+        Provider::Bttv => BTTV_ZERO_WIDTH.contains(&name),
+        Provider::Ffz => emote.get("modifier").and_then(|m| m.as_bool()).unwrap_or(false),
+        // bit 0 of the active emote flags
+        Provider::SevenTv => emote.get("flags").and_then(|f| f.as_u64()).is_some_and(|flags| flags & 1 == 1),
+        Provider::Twitch => false,
+    }
+}
+
+impl EmoteSet {
+    /// Accepts BTTV global (a list) and channel responses, FFZ room and set responses and
+    /// 7TV emote set and user responses.
+    pub fn from_json(provider: Provider, json: &str) -> Result<EmoteSet> {
+        let value: Value = serde_json::from_str(json)?;
+        // (list of emotes, key of the emote's name)
+        let (emotes, name_key) = match provider {
+            Provider::Bttv if value.is_array() => (array(Some(&value)).collect::<Vec<_>>(), "code"),
+            Provider::Bttv => {
+                let emotes = array(value.get("channelEmotes")).chain(array(value.get("sharedEmotes"))).collect();
+                (emotes, "code")
+            }
+            Provider::Ffz => {
+                let sets = value.get("sets").and_then(|sets| sets.as_object()).into_iter().flat_map(|sets| sets.values());
+                let emotes = sets.chain(value.get("set"))
+                    .flat_map(|set| array(set.get("emoticons")))
+                    .collect();
+                (emotes, "name")
+            }
+            Provider::SevenTv => {
+                let set = value.get("emote_set").unwrap_or(&value);
+                (array(set.get("emotes")).collect(), "name")
+            }
+            Provider::Twitch => return Err(LoadError::Format("twitch emotes come with the message".into())),
+        };
+
+        let mut set = EmoteSet::default();
+        for emote in emotes {
+            let (Some(id), Some(name)) = (emote.get("id").and_then(id_string), emote.get(name_key).and_then(|n| n.as_str())) else {
+                return Err(LoadError::Format(format!("emote without id or {}: {}", name_key, emote)));
+            };
+            let mut info = EmoteInfo::new(&id, name, provider);
+            info.zero_width = is_zero_width(provider, name, emote);
+            set.emotes.insert(name.into(), info);
+        }
+        Ok(set)
+    }
+
+    pub fn from_file(provider: Provider, path: impl AsRef<Path>) -> Result<EmoteSet> {
+        EmoteSet::from_json(provider, &fs::read_to_string(path)?)
+    }
+
+    /// Loads every `bttv*.json`, `ffz*.json` and `7tv*.json` in `dir`, other files are ignored.
+    /// When names clash, the file loaded last wins, files are loaded sorted by name.
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<EmoteSet> {
+        let mut files = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let provider = path.file_name().and_then(|name| name.to_str()).and_then(Provider::from_file_name);
+            if let Some(provider) = provider {
+                files.push((path, provider));
+            }
+        }
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut set = EmoteSet::default();
+        for (path, provider) in files {
+            set.extend(EmoteSet::from_file(provider, path)?);
+        }
+        Ok(set)
+    }
+
+    pub fn extend(&mut self, other: EmoteSet) {
+        self.emotes.extend(other.emotes);
+    }
+
+    pub fn len(&self) -> usize {
+        self.emotes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.emotes.is_empty()
+    }
+}
+
+impl EmoteProvider for EmoteSet {
+    fn emote(&self, word: &str) -> Option<EmoteInfo> {
+        self.emotes.get(word).cloned()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BTTV: &str = include_str!("../../testdata/emotes/bttv_channel.json");
+    const FFZ: &str = include_str!("../../testdata/emotes/ffz_room.json");
+    const SEVENTV: &str = include_str!("../../testdata/emotes/7tv_user.json");
+
+    #[test]
+    fn test_load_formats() {
+        let bttv = EmoteSet::from_json(Provider::Bttv, BTTV).unwrap();
+        assert_eq!(bttv.len(), 2);
+        assert_eq!(bttv.emote("SourPls").unwrap().id, "566ca38765dbbdab32ec0560");
+
+        let bttv_global = EmoteSet::from_json(Provider::Bttv, r#"[{"id": "54fa8f1401e468494b85b537", "code": ":tf:"}]"#).unwrap();
+        assert!(bttv_global.emote(":tf:").is_some());
+
+        let ffz = EmoteSet::from_json(Provider::Ffz, FFZ).unwrap();
+        assert_eq!(ffz.len(), 2);
+        assert_eq!(ffz.emote("OMEGALUL").unwrap().id, "381875");
+
+        let seventv = EmoteSet::from_json(Provider::SevenTv, SEVENTV).unwrap();
+        assert_eq!(seventv.len(), 2);
+        assert_eq!(seventv.emote("peepoHappy").unwrap().provider, Provider::SevenTv);
+        assert!(!seventv.emote("peepoHappy").unwrap().zero_width);
+        assert!(seventv.emote("RainTime").unwrap().zero_width);
+        assert!(seventv.emote("peepohappy").is_none());
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        assert!(matches!(EmoteSet::from_json(Provider::Bttv, "{"), Err(LoadError::Json(_))));
+        assert!(matches!(EmoteSet::from_json(Provider::Bttv, r#"[{"code": "x"}]"#), Err(LoadError::Format(_))));
+    }
+
+    #[test]
+    fn test_load_dir() {
+        let set = EmoteSet::from_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/emotes")).unwrap();
+        assert_eq!(set.len(), 6);
+        assert!(matches!(EmoteSet::from_dir("/nonexistent"), Err(LoadError::Io(_))));
+    }
+}
@@ -0,0 +1,24 @@
+use crate::irc::client::ClientError;
+use crate::irc::protocol::Message;
+use crate::irc::roomstate::RoomMode;
+
+/// Everything the client's iterator yields: server messages plus changes of the connection and channel state.
+// messages are by far the most common variant, boxing them would only add an allocation.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum Event {
+    Message(Message),
+    // registered with the server (001), sent again after every reconnect
+    Connected,
+    // the connection was lost unexpectedly
+    Disconnected,
+    // reconnect attempt n is about to start
+    Reconnecting(u32),
+    // something went wrong after connect() returned, e.g. why the connection was lost
+    Error(ClientError),
+    // a ROOMSTATE flipped a chat setting of a channel we were already in, follows the message
+    RoomModeChanged(String, RoomMode),
+    // (channel, login) of another user, from JOIN/PART once our own JOIN to the channel arrived
+    UserJoined(String, String),
+    UserLeft(String, String),
+}
@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet};
+use crate::irc::event::Event;
+use crate::irc::protocol::{Command, Message};
+
+/// Who is in the channels we joined, from the NAMES list (353 up to 366) and the JOIN/PART
+/// that follow it. Needs the `twitch.tv/membership` capability.
+///
+/// This is only an approximation: twitch sends JOIN/PART in batches every few seconds, and
+/// in channels with more than 1000 chatters NAMES lists only the moderators and no JOIN/PART
+/// arrive for regular users at all.
+#[derive(Debug, Default)]
+pub(crate) struct Membership {
+    chatters: HashMap<String, HashSet<String>>,
+    // NAMES lists still being received
+    names: HashMap<String, HashSet<String>>,
+}
+
+impl Membership {
+    /// Returns the event for someone joining or leaving, `own_nick` joining or leaving only
+    /// starts or ends the tracking of a channel.
+    pub(crate) fn handle(&mut self, msg: &Message, own_nick: &str) -> Option<Event> {
+        match msg.command {
+            // `353 <nick> = #channel :a b c`
+            Command::Names if msg.params.len() >= 4 => {
+                let names = self.names.entry(msg.params[2].clone()).or_default();
+                names.extend(msg.params[3].split_whitespace().map(|name| name.to_lowercase()));
+                None
+            }
+            // `366 <nick> #channel :End of /NAMES list`
+            Command::EndOfNames if msg.params.len() >= 2 => {
+                // merged, so JOINs that arrived while the list was coming in stay
+                let channel = &msg.params[1];
+                if let Some(names) = self.names.remove(channel) {
+                    self.chatters.entry(channel.clone()).or_default().extend(names);
+                }
+                None
+            }
+            Command::Join | Command::Part => {
+                let nick = msg.prefix.as_ref()?.nick.as_ref()?.to_lowercase();
+                let channel = msg.params.first()?;
+                if nick.eq_ignore_ascii_case(own_nick) {
+                    if msg.command == Command::Join {
+                        self.chatters.entry(channel.clone()).or_default().insert(nick);
+                    } else {
+                        self.chatters.remove(channel);
+                        self.names.remove(channel);
+                    }
+                    return None;
+                }
+                let chatters = self.chatters.get_mut(channel)?;
+                if msg.command == Command::Part {
+                    // or the list still being received would bring them back
+                    if let Some(names) = self.names.get_mut(channel) {
+                        names.remove(&nick);
+                    }
+                }
+                if msg.command == Command::Join {
+                    chatters.insert(nick.clone()).then(|| Event::UserJoined(channel.clone(), nick))
+                } else {
+                    chatters.remove(&nick).then(|| Event::UserLeft(channel.clone(), nick))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Sorted logins, `None` for a channel we're not in.
+    pub(crate) fn chatters(&self, channel: &str) -> Option<Vec<String>> {
+        let mut chatters = self.chatters.get(channel)?.iter().cloned().collect::<Vec<_>>();
+        chatters.sort();
+        Some(chatters)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::protocol::parse_line;
+
+    fn handle(membership: &mut Membership, line: &str) -> Option<Event> {
+        membership.handle(&parse_line(line).unwrap(), "bot")
+    }
+
+    #[test]
+    fn test_names_then_deltas() {
+        let mut membership = Membership::default();
+        assert!(handle(&mut membership, ":bot!bot@bot.tmi.twitch.tv JOIN #foo").is_none());
+        assert_eq!(membership.chatters("#foo").unwrap(), vec!["bot"]);
+
+        handle(&mut membership, ":bot.tmi.twitch.tv 353 bot = #foo :bot Alice bob");
+        handle(&mut membership, ":bot.tmi.twitch.tv 353 bot = #foo :carol");
+        // the list only counts once it's complete
+        assert_eq!(membership.chatters("#foo").unwrap(), vec!["bot"]);
+        handle(&mut membership, ":bot.tmi.twitch.tv 366 bot #foo :End of /NAMES list");
+        assert_eq!(membership.chatters("#foo").unwrap(), vec!["alice", "bob", "bot", "carol"]);
+
+        let event = handle(&mut membership, ":dave!dave@dave.tmi.twitch.tv JOIN #foo");
+        assert!(matches!(event, Some(Event::UserJoined(channel, user)) if channel == "#foo" && user == "dave"));
+        let event = handle(&mut membership, ":alice!alice@alice.tmi.twitch.tv PART #foo");
+        assert!(matches!(event, Some(Event::UserLeft(channel, user)) if channel == "#foo" && user == "alice"));
+        // already gone
+        assert!(handle(&mut membership, ":alice!alice@alice.tmi.twitch.tv PART #foo").is_none());
+        assert_eq!(membership.chatters("#foo").unwrap(), vec!["bob", "bot", "carol", "dave"]);
+
+        handle(&mut membership, ":bot!bot@bot.tmi.twitch.tv PART #foo");
+        assert_eq!(membership.chatters("#foo"), None);
+    }
+
+    #[test]
+    fn test_end_of_names_without_names() {
+        let mut membership = Membership::default();
+        handle(&mut membership, ":bot!bot@bot.tmi.twitch.tv JOIN #foo");
+        handle(&mut membership, ":alice!alice@alice.tmi.twitch.tv JOIN #foo");
+        handle(&mut membership, ":bot.tmi.twitch.tv 366 bot #foo :End of /NAMES list");
+        assert_eq!(membership.chatters("#foo").unwrap(), vec!["alice", "bot"]);
+    }
+
+    #[test]
+    fn test_deltas_during_names() {
+        let mut membership = Membership::default();
+        handle(&mut membership, ":bot!bot@bot.tmi.twitch.tv JOIN #foo");
+        handle(&mut membership, ":bot.tmi.twitch.tv 353 bot = #foo :bot alice bob");
+        let event = handle(&mut membership, ":carol!carol@carol.tmi.twitch.tv JOIN #foo");
+        assert!(matches!(event, Some(Event::UserJoined(channel, user)) if channel == "#foo" && user == "carol"));
+        handle(&mut membership, ":bob!bob@bob.tmi.twitch.tv PART #foo");
+        handle(&mut membership, ":bot.tmi.twitch.tv 366 bot #foo :End of /NAMES list");
+        assert_eq!(membership.chatters("#foo").unwrap(), vec!["alice", "bot", "carol"]);
+    }
+
+    #[test]
+    fn test_untracked_channel() {
+        let mut membership = Membership::default();
+        assert!(handle(&mut membership, ":dave!dave@dave.tmi.twitch.tv JOIN #bar").is_none());
+        assert_eq!(membership.chatters("#bar"), None);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::irc::protocol::{Command, Message};
+
+/// The `msg-id` of a NOTICE, see https://dev.twitch.tv/docs/chat/irc/#notice-reference
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoticeKind {
+    // our message was not sent
+    RateLimited,
+    Duplicate,
+    Banned,
+    TimedOut,
+    ChannelSuspended,
+    AccountSuspended,
+    FollowersOnly,
+    SubsOnly,
+    EmoteOnly,
+    SlowMode,
+    UniqueChat,
+    VerifiedPhoneRequired,
+    VerifiedEmailRequired,
+    UnrecognizedCommand,
+    // a room setting changed
+    SlowOn,
+    SlowOff,
+    FollowersOn,
+    FollowersOff,
+    SubsOn,
+    SubsOff,
+    EmoteOnlyOn,
+    EmoteOnlyOff,
+    UniqueChatOn,
+    UniqueChatOff,
+    // anything not listed above, kept verbatim
+    Other(String),
+}
+
+impl NoticeKind {
+    pub fn from_msg_id(msg_id: &str) -> NoticeKind {
+        match msg_id {
+            "msg_ratelimit" => NoticeKind::RateLimited,
+            "msg_duplicate" => NoticeKind::Duplicate,
+            "msg_banned" => NoticeKind::Banned,
+            "msg_timedout" => NoticeKind::TimedOut,
+            "msg_channel_suspended" => NoticeKind::ChannelSuspended,
+            "msg_suspended" => NoticeKind::AccountSuspended,
+            "msg_followersonly" | "msg_followersonly_zero" | "msg_followersonly_followed" => NoticeKind::FollowersOnly,
+            "msg_subsonly" => NoticeKind::SubsOnly,
+            "msg_emoteonly" => NoticeKind::EmoteOnly,
+            "msg_slowmode" => NoticeKind::SlowMode,
+            "msg_r9k" => NoticeKind::UniqueChat,
+            "msg_requires_verified_phone_number" => NoticeKind::VerifiedPhoneRequired,
+            "msg_verified_email" => NoticeKind::VerifiedEmailRequired,
+            "unrecognized_cmd" => NoticeKind::UnrecognizedCommand,
+            "slow_on" => NoticeKind::SlowOn,
+            "slow_off" => NoticeKind::SlowOff,
+            "followers_on" | "followers_on_zero" => NoticeKind::FollowersOn,
+            "followers_off" => NoticeKind::FollowersOff,
+            "subs_on" => NoticeKind::SubsOn,
+            "subs_off" => NoticeKind::SubsOff,
+            "emote_only_on" => NoticeKind::EmoteOnlyOn,
+            "emote_only_off" => NoticeKind::EmoteOnlyOff,
+            "r9k_on" => NoticeKind::UniqueChatOn,
+            "r9k_off" => NoticeKind::UniqueChatOff,
+            _ => NoticeKind::Other(msg_id.into()),
+        }
+    }
+
+    /// Whether the notice answers a message of ours that twitch didn't deliver.
+    pub fn is_send_failure(&self) -> bool {
+        match self {
+            NoticeKind::Other(msg_id) => msg_id.starts_with("msg_"),
+            kind => matches!(kind,
+                NoticeKind::RateLimited | NoticeKind::Duplicate | NoticeKind::Banned | NoticeKind::TimedOut
+                | NoticeKind::ChannelSuspended | NoticeKind::AccountSuspended | NoticeKind::FollowersOnly
+                | NoticeKind::SubsOnly | NoticeKind::EmoteOnly | NoticeKind::SlowMode | NoticeKind::UniqueChat
+                | NoticeKind::VerifiedPhoneRequired | NoticeKind::VerifiedEmailRequired),
+        }
+    }
+}
+
+impl Message {
+    /// `None` for anything but a NOTICE, and for a NOTICE without `msg-id` like the login failure.
+    pub fn notice_kind(&self) -> Option<NoticeKind> {
+        if self.command != Command::Notice {
+            return None;
+        }
+        self.tags.get("msg-id").map(|id| NoticeKind::from_msg_id(id))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::protocol::parse_line;
+
+    #[test]
+    fn test_notice_kind() {
+        let line = "@msg-id=msg_banned :tmi.twitch.tv NOTICE #bar :You are permanently banned from talking in bar.";
+        let kind = parse_line(line).unwrap().notice_kind().unwrap();
+        assert_eq!(kind, NoticeKind::Banned);
+        assert!(kind.is_send_failure());
+
+        let line = "@msg-id=slow_on :tmi.twitch.tv NOTICE #bar :This room is now in slow mode.";
+        let kind = parse_line(line).unwrap().notice_kind().unwrap();
+        assert_eq!(kind, NoticeKind::SlowOn);
+        assert!(!kind.is_send_failure());
+    }
+
+    #[test]
+    fn test_notice_kind_other() {
+        let line = "@msg-id=msg_channel_blocked :tmi.twitch.tv NOTICE #bar :Your message wasn't posted.";
+        let kind = parse_line(line).unwrap().notice_kind().unwrap();
+        assert_eq!(kind, NoticeKind::Other("msg_channel_blocked".into()));
+        assert!(kind.is_send_failure());
+    }
+
+    #[test]
+    fn test_notice_kind_missing() {
+        let msg = parse_line(":tmi.twitch.tv NOTICE * :Login authentication failed").unwrap();
+        assert_eq!(msg.notice_kind(), None);
+        let msg = parse_line("@msg-id=msg_banned :tmi.twitch.tv PRIVMSG #bar :hi").unwrap();
+        assert_eq!(msg.notice_kind(), None);
+    }
+}
@@ -33,6 +33,11 @@ pub enum Command {
     UserState,
     RoomState,
     UserNotice,
+    ClearChat,
+    ClearMsg,
+    Reconnect,
+    // anything not listed above, kept verbatim
+    Unknown(String),
 }
 
 #[derive(Debug, Clone)]
@@ -50,11 +55,14 @@ impl Display for Prefix {
             s.push_str(nick);
         }
         if let Some(user) = &self.user {
-            s.push_str("!");
+            s.push('!');
             s.push_str(user);
         }
         if let Some(host) = &self.host {
-            s.push_str("@");
+            // a bare server name is stored as host without a nick
+            if self.nick.is_some() {
+                s.push('@');
+            }
             s.push_str(host);
         }
 
@@ -69,22 +77,76 @@ pub struct Message {
     pub command: Command,
     pub params: Vec<String>,
     _had_trailing: bool,
+    // tags written without a value (`@key`), so they are written back that way
+    _valueless_tags: Vec<String>,
     _original_line: String,
 }
 
-#[derive(Debug, PartialEq)]
+/// Where an emote comes from. Twitch's own are in the `emotes` tag, the others are matched by word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Provider {
+    Twitch,
+    Bttv,
+    Ffz,
+    SevenTv,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct EmoteInfo {
     pub id: String,
     pub emote: String,
+    pub provider: Provider,
+    // drawn on top of the emote before it instead of on its own
+    pub zero_width: bool,
+    // zero-width emotes drawn on top of this one
+    pub modifiers: Vec<EmoteInfo>,
 }
 
-#[derive(Debug, PartialEq)]
+impl EmoteInfo {
+    pub fn new(id: &str, emote: &str, provider: Provider) -> EmoteInfo {
+        EmoteInfo {
+            id: id.into(),
+            emote: emote.into(),
+            provider,
+            zero_width: false,
+            modifiers: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum RichText {
     Text(String),
     Emote(EmoteInfo),
+    // without the @
+    Mention(String),
+    Link(String),
+    // only in messages with a `bits` tag
+    Cheer { prefix: String, bits: u32 },
 }
 
 impl Message {
+    /// A message to send. `trailing` is appended as the last parameter and always written after ':'.
+    /// There is no original line, `original_line` is empty.
+    pub fn new(command: Command, params: &[&str], trailing: Option<&str>) -> Message {
+        let mut params = params.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        params.extend(trailing.map(|t| t.to_string()));
+        Message {
+            tags: IndexMap::new(),
+            prefix: None,
+            command,
+            params,
+            _had_trailing: trailing.is_some(),
+            _valueless_tags: vec![],
+            _original_line: String::new(),
+        }
+    }
+
+    pub fn with_tag(mut self, key: &str, value: &str) -> Message {
+        self.tags.insert(key.into(), value.into());
+        self
+    }
+
     pub fn original_line(&self) -> String {
         self._original_line.clone()
     }
@@ -100,15 +162,26 @@ impl Message {
     }
 
     pub fn is_valid_privmsg(&self) -> bool {
-        return self.params.len() == 2 && self.prefix.as_ref().is_some_and(|p| p.nick.is_some());
+        self.params.len() == 2 && self.prefix.as_ref().is_some_and(|p| p.nick.is_some())
     }
 
     pub fn is_channel_message(&self) -> bool {
-        return self.is_valid_privmsg() && self.params[0].starts_with("#");
+        self.is_valid_privmsg() && self.params[0].starts_with("#")
     }
 
     pub fn is_private_message(&self) -> bool {
-        return self.is_valid_privmsg() && !self.params[0].starts_with("#");
+        self.is_valid_privmsg() && !self.params[0].starts_with("#")
+    }
+
+    /// The text of a PRIVMSG or NOTICE, for `/me` messages without the CTCP wrapping.
+    pub fn text(&self) -> Option<&str> {
+        if !matches!(self.command, Command::Privmsg | Command::Notice) {
+            return None;
+        }
+        match self.ctcp() {
+            Some(("ACTION", text)) => Some(text),
+            _ => self.params.get(1).map(|text| text.as_str()),
+        }
     }
 
     pub fn with_command(&self, new_command: Command) -> Message {
@@ -118,10 +191,47 @@ impl Message {
             command: new_command,
             params: self.params.clone(),
             _had_trailing: self._had_trailing,
+            _valueless_tags: self._valueless_tags.clone(),
             _original_line: self._original_line.clone(),
         }
     }
 
+    /// The user a CLEARCHAT or CLEARMSG is aimed at.
+    /// `None` for a CLEARCHAT that clears the whole chat.
+    pub fn target_user(&self) -> Option<&String> {
+        match self.command {
+            Command::ClearChat => self.params.get(1),
+            Command::ClearMsg => self.tags.get("login"),
+            _ => None,
+        }
+    }
+
+    /// Timeout length in seconds. A CLEARCHAT with a target user but no duration is a permanent ban.
+    pub fn ban_duration(&self) -> Option<u64> {
+        self.tags.get("ban-duration").and_then(|d| d.parse().ok())
+    }
+
+    pub fn is_ban(&self) -> bool {
+        self.command == Command::ClearChat && self.target_user().is_some() && self.ban_duration().is_none()
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        self.command == Command::ClearChat && self.ban_duration().is_some()
+    }
+
+    pub fn target_msg_id(&self) -> Option<&String> {
+        self.tags.get("target-msg-id")
+    }
+
+    pub fn target_user_id(&self) -> Option<&String> {
+        self.tags.get("target-user-id")
+    }
+
+    /// Server timestamp in milliseconds since the unix epoch.
+    pub fn tmi_sent_ts(&self) -> Option<u64> {
+        self.tags.get("tmi-sent-ts").and_then(|ts| ts.parse().ok())
+    }
+
     fn emote_tuples(&self) -> Result<Vec<(&str, usize, usize)>> {
         if !self.is_channel_message() {
             return Ok(vec![]);
@@ -148,7 +258,7 @@ impl Message {
         }
 
         // not sure if emotes are guaranteed to be sorted, but just to be safe.
-        emotes.sort_by(|a, b| a.1.cmp(&b.1));
+        emotes.sort_by_key(|a| a.1);
 
         Ok(emotes)
     }
@@ -158,25 +268,32 @@ impl Message {
 
         let mut rich_parts: Vec<RichText> = vec![];
 
-        let text = self.params[1].as_str();
+        // in `/me` messages the positions are relative to the text without the CTCP wrapping
+        let Some(text) = self.text().or_else(|| self.params.get(1).map(String::as_str)) else {
+            return Ok(vec![]);
+        };
+        // the positions in the tag count code points, not bytes.
+        // offsets[i] is where code point i starts, the last entry is the end of the text.
+        let offsets = text.char_indices().map(|(i, _)| i).chain(std::iter::once(text.len())).collect::<Vec<_>>();
+        let byte_range = |start: usize, end: usize| -> Option<&str> {
+            text.get(*offsets.get(start)?..*offsets.get(end)?)
+        };
         let mut last_end: usize = 0;
 
         for (emote, start, end) in emotes {
-            let text_part = text.get(last_end..start).ok_or_else(|| ParseError::InvalidRange(last_end, Some(start)))?;
-            let emote_part = text.get(start..=end).ok_or_else(|| ParseError::InvalidRange(start, Some(end + 1)))?;
+            let text_part = byte_range(last_end, start).ok_or(ParseError::InvalidRange(last_end, Some(start)))?;
+            let emote_part = byte_range(start, end + 1).ok_or_else(|| ParseError::InvalidRange(start, Some(end + 1)))?;
             last_end = end + 1;
 
             if !text_part.is_empty() {
                 rich_parts.push(RichText::Text(text_part.into()));
             }
-            rich_parts.push(RichText::Emote(EmoteInfo {
-                id: emote.into(),
-                emote: emote_part.into(),
-            }))
+            rich_parts.push(RichText::Emote(EmoteInfo::new(emote, emote_part, Provider::Twitch)))
         }
 
-        if last_end < text.len() {
-            let last_text_part = text.get(last_end..).ok_or_else(|| ParseError::InvalidRange(last_end, None))?;
+        let char_count = offsets.len() - 1;
+        if last_end < char_count {
+            let last_text_part = byte_range(last_end, char_count).ok_or(ParseError::InvalidRange(last_end, None))?;
             rich_parts.push(RichText::Text(last_text_part.into()));
         }
 
@@ -189,27 +306,32 @@ impl Display for Message {
         let mut s = String::new();
 
         let tags = self.tags.iter().map(|(k, v)| {
-            format!("{}={}", k, v)
+            if v.is_empty() && self._valueless_tags.contains(k) {
+                k.clone()
+            } else {
+                format!("{}={}", k, escape_tag_value(v))
+            }
         }).collect::<Vec<_>>().join(";");
 
-        if tags.len() > 0 {
-            s.push_str("@");
+        if !tags.is_empty() {
+            s.push('@');
             s.push_str(&tags);
-            s.push_str(" ");
+            s.push(' ');
         }
 
         if let Some(prefix) = &self.prefix {
+            s.push(':');
             s.push_str(&format!("{}", prefix));
-            s.push_str(" ");
+            s.push(' ');
         }
 
         s.push_str(&map_command_back(&self.command));
 
         for (i, param) in self.params.iter().enumerate() {
-            if i == self.params.len() - 1 && (self._had_trailing || param.contains(' ') || param.starts_with(":")) {
+            if i == self.params.len() - 1 && (self._had_trailing || param.is_empty() || param.contains(' ') || param.starts_with(":")) {
                 s.push_str(" :");
             } else {
-                s.push_str(" ");
+                s.push(' ');
             }
             s.push_str(param);
         }
@@ -218,9 +340,9 @@ impl Display for Message {
     }
 }
 
-fn extract_tags(line: &str) -> Result<(IndexMap<String, String>, &str)> {
+fn extract_tags(line: &str) -> Result<(IndexMap<String, String>, Vec<String>, &str)> {
     if !line.starts_with("@") {
-        return Ok((IndexMap::new(), line));
+        return Ok((IndexMap::new(), vec![], line));
     }
 
     let (tag_string, rest) = if let Some(end_pos) = line.find(" ") {
@@ -230,16 +352,55 @@ fn extract_tags(line: &str) -> Result<(IndexMap<String, String>, &str)> {
     };
 
     let mut tags = IndexMap::new();
+    let mut valueless = vec![];
 
     for part in tag_string.split(";") {
         if let Some((key, value)) = part.split_once("=") {
-            tags.insert(key.into(), value.into());
+            tags.insert(key.into(), unescape_tag_value(value));
         } else {
             tags.insert(part.into(), "".into());
+            valueless.push(part.into());
         }
     }
 
-    Ok((tags, rest))
+    Ok((tags, valueless, rest))
+}
+
+// https://ircv3.net/specs/extensions/message-tags#escaping-values
+fn unescape_tag_value(value: &str) -> String {
+    let mut s = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        // a trailing lone backslash is dropped, unknown escapes lose the backslash.
+        match chars.next() {
+            Some(':') => s.push(';'),
+            Some('s') => s.push(' '),
+            Some('r') => s.push('\r'),
+            Some('n') => s.push('\n'),
+            Some(other) => s.push(other),
+            None => (),
+        }
+    }
+    s
+}
+
+fn escape_tag_value(value: &str) -> String {
+    let mut s = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => s.push_str("\\:"),
+            ' ' => s.push_str("\\s"),
+            '\\' => s.push_str("\\\\"),
+            '\r' => s.push_str("\\r"),
+            '\n' => s.push_str("\\n"),
+            _ => s.push(c),
+        }
+    }
+    s
 }
 
 fn extract_prefix(line: &str) -> Result<(Option<Prefix>, &str)> {
@@ -274,9 +435,9 @@ fn extract_prefix(line: &str) -> Result<(Option<Prefix>, &str)> {
 
 fn extract_command(line: &str) -> Result<(Command, &str)> {
     if let Some((cmd, rest)) = line.split_once(" ") {
-        Ok((map_command(cmd)?, rest))
-    } else if line.len() > 0 {
-        Ok((map_command(line)?, ""))
+        Ok((map_command(cmd), rest))
+    } else if !line.is_empty() {
+        Ok((map_command(line), ""))
     } else {
         Err(ParseError::MissingCommand)
     }
@@ -284,8 +445,8 @@ fn extract_command(line: &str) -> Result<(Command, &str)> {
 
 
 fn extract_params(line: &str) -> Result<(Vec<String>, bool, &str)> {
-    if line.starts_with(":") {
-        return Ok((vec![line[1..].into()], true, &line[line.len()..]));
+    if let Some(trailing) = line.strip_prefix(":") {
+        return Ok((vec![trailing.into()], true, &line[line.len()..]));
     }
 
     if let Some(trail_pos) = line.find(" :") {
@@ -305,8 +466,8 @@ fn to_params(param_string: &str, trailing: Option<&str>) -> Vec<String> {
     } else {
         param_string.split(" ").map(|s| s.into()).collect::<Vec<String>>()
     };
-    if trailing.is_some() {
-        params.push(trailing.unwrap().into());
+    if let Some(trailing) = trailing {
+        params.push(trailing.into());
     }
     params
 }
@@ -315,7 +476,7 @@ fn to_params(param_string: &str, trailing: Option<&str>) -> Vec<String> {
 pub fn parse_line(line: &str) -> Result<Message> {
     let original_line: String = line.into();
 
-    let (tags, line) = extract_tags(line)?;
+    let (tags, valueless_tags, line) = extract_tags(line)?;
     let (prefix, line) = extract_prefix(line)?;
     let (command, line) = extract_command(line)?;
     let (params, had_trailing, _) = extract_params(line)?;
@@ -326,29 +487,43 @@ pub fn parse_line(line: &str) -> Result<Message> {
         command,
         params,
         _had_trailing: had_trailing,
+        _valueless_tags: valueless_tags,
         _original_line: original_line,
     })
 }
 
 
-fn map_command(cmd: &str) -> Result<Command> {
+/// Like `parse_line`, but rejects commands that don't map to a known `Command`.
+pub fn parse_line_strict(line: &str) -> Result<Message> {
+    let msg = parse_line(line)?;
+    if let Command::Unknown(cmd) = msg.command {
+        return Err(ParseError::UnknownCommand(cmd));
+    }
+    Ok(msg)
+}
+
+
+fn map_command(cmd: &str) -> Command {
     match cmd {
-        "001" => Ok(Command::Ready),
-        "002" | "003" | "004" | "375" | "372" | "376" => Ok(Command::Misc(cmd.into())),
-        "353" => Ok(Command::Names),
-        "366" => Ok(Command::EndOfNames),
-        "PING" => Ok(Command::Ping),
-        "PONG" => Ok(Command::Pong),
-        "PRIVMSG" => Ok(Command::Privmsg),
-        "NOTICE" => Ok(Command::Notice),
-        "JOIN" => Ok(Command::Join),
-        "PART" => Ok(Command::Part),
-        "CAP" => Ok(Command::Cap),
-        "GLOBALUSERSTATE" => Ok(Command::GlobalUserState),
-        "USERSTATE" => Ok(Command::UserState),
-        "ROOMSTATE" => Ok(Command::RoomState),
-        "USERNOTICE" => Ok(Command::UserNotice),
-        _ => Err(ParseError::UnknownCommand(cmd.to_string()))
+        "001" => Command::Ready,
+        "002" | "003" | "004" | "375" | "372" | "376" => Command::Misc(cmd.into()),
+        "353" => Command::Names,
+        "366" => Command::EndOfNames,
+        "PING" => Command::Ping,
+        "PONG" => Command::Pong,
+        "PRIVMSG" => Command::Privmsg,
+        "NOTICE" => Command::Notice,
+        "JOIN" => Command::Join,
+        "PART" => Command::Part,
+        "CAP" => Command::Cap,
+        "GLOBALUSERSTATE" => Command::GlobalUserState,
+        "USERSTATE" => Command::UserState,
+        "ROOMSTATE" => Command::RoomState,
+        "USERNOTICE" => Command::UserNotice,
+        "CLEARCHAT" => Command::ClearChat,
+        "CLEARMSG" => Command::ClearMsg,
+        "RECONNECT" => Command::Reconnect,
+        _ => Command::Unknown(cmd.into()),
     }
 }
 
@@ -369,6 +544,10 @@ fn map_command_back(cmd: &Command) -> String {
         Command::UserState => "USERSTATE".into(),
         Command::RoomState => "ROOMSTATE".into(),
         Command::UserNotice => "USERNOTICE".into(),
+        Command::ClearChat => "CLEARCHAT".into(),
+        Command::ClearMsg => "CLEARMSG".into(),
+        Command::Reconnect => "RECONNECT".into(),
+        Command::Unknown(v) => v.into(),
     }
 }
 
@@ -483,10 +662,109 @@ mod tests {
     fn test_unknown_command() {
         let line = "UNKNOWNCOMMAND";
         let result = parse_line(line);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap().command, Command::Unknown("UNKNOWNCOMMAND".into()));
+    }
+
+    #[test]
+    fn test_unknown_command_roundtrip() {
+        let line = ":tmi.twitch.tv HOSTTARGET #channel :otherchannel 10";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.command, Command::Unknown("HOSTTARGET".into()));
+        assert_eq!(format!("{}", msg), line);
+
+        let msg = parse_line(":tmi.twitch.tv 421 nick WHO :Unknown command").unwrap();
+        assert_eq!(msg.command, Command::Unknown("421".into()));
+        assert_eq!(msg.params, vec!["nick", "WHO", "Unknown command"]);
+    }
+
+    #[test]
+    fn test_unknown_command_strict() {
+        let line = "UNKNOWNCOMMAND";
+        let result = parse_line_strict(line);
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), ParseError::UnknownCommand("UNKNOWNCOMMAND".into()))
     }
 
+    #[test]
+    fn test_tags_unescape() {
+        let line = r"@system-msg=5\sraiders\sfrom\sfoo;a=semi\:colon;b=back\\slash;c=cr\rlf\n;d=un\known;e=trailing\ :tmi.twitch.tv USERNOTICE #channel";
+        let result = parse_line(line);
+        assert!(result.is_ok());
+        let msg = result.unwrap();
+        assert_eq!(msg.tags["system-msg"], "5 raiders from foo");
+        assert_eq!(msg.tags["a"], "semi;colon");
+        assert_eq!(msg.tags["b"], "back\\slash");
+        assert_eq!(msg.tags["c"], "cr\rlf\n");
+        assert_eq!(msg.tags["d"], "unknown");
+        assert_eq!(msg.tags["e"], "trailing");
+    }
+
+    #[test]
+    fn test_tags_roundtrip() {
+        let line = r"@system-msg=5\sraiders;a=semi\:colon;b=back\\slash;c=cr\rlf\n;empty= :tmi.twitch.tv USERNOTICE #channel";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(format!("{}", msg), line);
+    }
+
+    #[test]
+    fn test_valueless_tags_roundtrip() {
+        let line = "@foo;bar=1;baz :tmi.twitch.tv USERNOTICE #channel";
+        let mut msg = parse_line(line).unwrap();
+        assert_eq!(msg.tags["foo"], "");
+        assert_eq!(format!("{}", msg), line);
+        // given a value, it's written with one
+        msg.tags.insert("foo".into(), "x".into());
+        assert_eq!(format!("{}", msg), "@foo=x;bar=1;baz :tmi.twitch.tv USERNOTICE #channel");
+    }
+
+    #[test]
+    fn test_new_message() {
+        let msg = Message::new(Command::Privmsg, &["#foo"], Some("hi"))
+            .with_tag("reply-parent-msg-id", "b34ccfc7-4977-403a-8a94-33c6bac34fb8");
+        assert_eq!(format!("{}", msg), "@reply-parent-msg-id=b34ccfc7-4977-403a-8a94-33c6bac34fb8 PRIVMSG #foo :hi");
+        assert_eq!(format!("{}", Message::new(Command::Join, &["#foo"], None)), "JOIN #foo");
+        assert_eq!(format!("{}", Message::new(Command::Privmsg, &["#foo"], Some(""))), "PRIVMSG #foo :");
+    }
+
+    #[test]
+    fn test_clearchat_timeout() {
+        let line = "@ban-duration=350;room-id=12345678;target-user-id=87654321;tmi-sent-ts=1642719320727 :tmi.twitch.tv CLEARCHAT #dallas :ronni";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.command, Command::ClearChat);
+        assert_eq!(msg.target_user().unwrap(), "ronni");
+        assert_eq!(msg.target_user_id().unwrap(), "87654321");
+        assert_eq!(msg.ban_duration(), Some(350));
+        assert_eq!(msg.tmi_sent_ts(), Some(1642719320727));
+        assert!(msg.is_timeout());
+        assert!(!msg.is_ban());
+    }
+
+    #[test]
+    fn test_clearchat_ban_and_clear() {
+        let line = "@room-id=12345678;target-user-id=87654321;tmi-sent-ts=1642715756806 :tmi.twitch.tv CLEARCHAT #dallas :ronni";
+        let msg = parse_line(line).unwrap();
+        assert!(msg.is_ban());
+        assert_eq!(msg.ban_duration(), None);
+
+        let line = "@room-id=12345678;tmi-sent-ts=1642715695392 :tmi.twitch.tv CLEARCHAT #dallas";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.target_user(), None);
+        assert!(!msg.is_ban());
+        assert!(!msg.is_timeout());
+    }
+
+    #[test]
+    fn test_clearmsg() {
+        let line = "@login=foo;room-id=;target-msg-id=94e6c7ff-bf98-4faa-af5d-7ad633a158a9;tmi-sent-ts=1642720582342 :tmi.twitch.tv CLEARMSG #bar :what a great day";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.command, Command::ClearMsg);
+        assert_eq!(msg.target_user().unwrap(), "foo");
+        assert_eq!(msg.target_msg_id().unwrap(), "94e6c7ff-bf98-4faa-af5d-7ad633a158a9");
+        assert_eq!(msg.tmi_sent_ts(), Some(1642720582342));
+        assert_eq!(msg.params[1], "what a great day");
+    }
+
     #[test]
     fn test_emotes_none() {
         let line = "@emotes= :nick!user@host PRIVMSG #channel :nothing";
@@ -510,10 +788,27 @@ mod tests {
         assert!(emotes_result.is_ok());
         let emotes = emotes_result.unwrap();
         assert_eq!(emotes.len(), 1);
-        assert_eq!(emotes[0], RichText::Emote(EmoteInfo {
-            id: "86".into(),
-            emote: "BibleThump".into(),
-        }));
+        assert_eq!(emotes[0], RichText::Emote(EmoteInfo::new("86", "BibleThump", Provider::Twitch)));
+    }
+
+    #[test]
+    fn test_emotes_action() {
+        let line = "@emotes=25:6-10 :nick!user@host PRIVMSG #channel :\x01ACTION waves Kappa\x01";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.emotes().unwrap(), vec![
+            RichText::Text("waves ".into()),
+            RichText::Emote(EmoteInfo::new("25", "Kappa", Provider::Twitch)),
+        ]);
+    }
+
+    #[test]
+    fn test_emotes_without_text() {
+        // most subs come without a message
+        let msg = parse_line("@msg-id=sub :tmi.twitch.tv USERNOTICE #dallas").unwrap();
+        assert_eq!(msg.emotes().unwrap(), vec![]);
+        let msg = parse_line(":n!u@h PRIVMSG #dallas").unwrap();
+        assert_eq!(msg.emotes().unwrap(), vec![]);
+        assert_eq!(msg.rich_text(&[]).unwrap(), vec![]);
     }
 
     #[test]
@@ -528,15 +823,9 @@ mod tests {
         assert_eq!(emotes.len(), 5);
 
         assert_eq!(emotes[0], RichText::Text("This is a ".into()));
-        assert_eq!(emotes[1], RichText::Emote(EmoteInfo {
-            id: "86".into(),
-            emote: "BibleThump".into(),
-        }));
+        assert_eq!(emotes[1], RichText::Emote(EmoteInfo::new("86", "BibleThump", Provider::Twitch)));
         assert_eq!(emotes[2], RichText::Text(" test with emotes ".into()));
-        assert_eq!(emotes[3], RichText::Emote(EmoteInfo {
-            id: "46".into(),
-            emote: "SSSsss".into(),
-        }));
+        assert_eq!(emotes[3], RichText::Emote(EmoteInfo::new("46", "SSSsss", Provider::Twitch)));
         assert_eq!(emotes[4], RichText::Text(" yay \\o/".into()));
     }
 
@@ -550,7 +839,7 @@ mod tests {
         assert!(emotes_result.is_err());
 
         match emotes_result {
-            Ok(_) => assert!(false),
+            Ok(_) => unreachable!(),
             Err(e) => {
                 assert_eq!(e, ParseError::InvalidRange(0, Some(10)));
             }
@@ -567,15 +856,55 @@ mod tests {
         assert!(emotes_result.is_ok());
         let emotes = emotes_result.unwrap();
         assert_eq!(emotes.len(), 3);
-        assert_eq!(emotes[0], RichText::Emote(EmoteInfo {
-            id: "86".into(),
-            emote: "BibleThump".into(),
-        }));
+        assert_eq!(emotes[0], RichText::Emote(EmoteInfo::new("86", "BibleThump", Provider::Twitch)));
         assert_eq!(emotes[1], RichText::Text(" ".into()));
-        assert_eq!(emotes[2], RichText::Emote(EmoteInfo {
-            id: "86".into(),
-            emote: "BibleThump".into(),
-        }));
+        assert_eq!(emotes[2], RichText::Emote(EmoteInfo::new("86", "BibleThump", Provider::Twitch)));
     }
-}
 
+    fn emote(id: &str, emote: &str) -> RichText {
+        RichText::Emote(EmoteInfo::new(id, emote, Provider::Twitch))
+    }
+
+    // positions in the emotes tag count code points, these messages have multi-byte ones in front of emotes
+    #[test]
+    fn test_emotes_unicode() {
+        let corpus = [
+            // emoji, 4 bytes each
+            ("@emotes=25:3-7 :nick!user@host PRIVMSG #channel :😂😂 Kappa", vec![
+                RichText::Text("😂😂 ".into()),
+                emote("25", "Kappa"),
+            ]),
+            // CJK, 3 bytes each
+            ("@emotes=25:4-8,15-19 :nick!user@host PRIVMSG #channel :日本語 Kappa 中文测试 Kappa!", vec![
+                RichText::Text("日本語 ".into()),
+                emote("25", "Kappa"),
+                RichText::Text(" 中文测试 ".into()),
+                emote("25", "Kappa"),
+                RichText::Text("!".into()),
+            ]),
+            // e + combining acute accent counts as two code points
+            ("@emotes=86:9-18 :nick!user@host PRIVMSG #channel :cafe\u{301} ok BibleThump ü", vec![
+                RichText::Text("cafe\u{301} ok ".into()),
+                emote("86", "BibleThump"),
+                RichText::Text(" ü".into()),
+            ]),
+            // emoji joined with zero width joiners, 5 code points
+            ("@emotes=25:6-10 :nick!user@host PRIVMSG #channel :👨‍👩‍👧 Kappa", vec![
+                RichText::Text("👨‍👩‍👧 ".into()),
+                emote("25", "Kappa"),
+            ]),
+        ];
+        for (line, expected) in corpus {
+            let msg = parse_line(line).unwrap();
+            assert_eq!(msg.emotes().unwrap(), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_emotes_unicode_out_of_range() {
+        // in bytes this would fit, in code points it doesn't
+        let line = "@emotes=25:3-7 :nick!user@host PRIVMSG #channel :😂😂 Kapp";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.emotes().unwrap_err(), ParseError::InvalidRange(3, Some(8)));
+    }
+}
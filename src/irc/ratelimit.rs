@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How much a user may send, see https://dev.twitch.tv/docs/chat/#rate-limits
+/// Going over the message limit gets the account muted for 30 minutes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimits {
+    // PRIVMSGs per window
+    pub messages: u32,
+    // PRIVMSGs per window to channels where we are moderator, VIP or broadcaster
+    pub elevated_messages: u32,
+    pub message_window: Duration,
+    // channels joined per window
+    pub joins: u32,
+    pub join_window: Duration,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            messages: 20,
+            elevated_messages: 100,
+            message_window: Duration::from_secs(30),
+            joins: 20,
+            join_window: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The times of the sends in the last `window`, allows at most `capacity` of them in any
+/// window. A burst of `capacity` has to wait until the first of it is a `window` old.
+#[derive(Debug, Clone)]
+pub(crate) struct SendLog {
+    capacity: usize,
+    window: Duration,
+    sends: VecDeque<Instant>,
+}
+
+impl SendLog {
+    pub(crate) fn new(capacity: u32, window: Duration) -> SendLog {
+        SendLog {
+            capacity: capacity.max(1) as usize,
+            window,
+            sends: VecDeque::new(),
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self.sends.front().is_some_and(|&sent| now.saturating_duration_since(sent) >= self.window) {
+            self.sends.pop_front();
+        }
+    }
+
+    /// Never allows more than `capacity` at once, those have to be split up by the caller.
+    pub(crate) fn try_take(&mut self, amount: usize, now: Instant) -> bool {
+        self.expire(now);
+        if self.sends.len() + amount > self.capacity {
+            return false;
+        }
+        self.sends.extend(std::iter::repeat_n(now, amount));
+        true
+    }
+
+    // records sends that count against this log without needing its permission
+    fn record(&mut self, amount: usize, now: Instant) {
+        self.expire(now);
+        self.sends.extend(std::iter::repeat_n(now, amount));
+    }
+}
+
+/// What sending a line costs.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Cost {
+    Free,
+    Message { elevated: bool },
+    // number of channels joined
+    Join(usize),
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    messages: SendLog,
+    elevated_messages: SendLog,
+    joins: SendLog,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: &RateLimits) -> RateLimiter {
+        RateLimiter {
+            messages: SendLog::new(limits.messages, limits.message_window),
+            elevated_messages: SendLog::new(limits.elevated_messages, limits.message_window),
+            joins: SendLog::new(limits.joins, limits.join_window),
+        }
+    }
+
+    /// The most channels a single JOIN may have, more would never be allowed.
+    pub(crate) fn max_joins(&self) -> usize {
+        self.joins.capacity
+    }
+
+    /// Both message logs count every message, the cost decides which one has to allow it.
+    pub(crate) fn try_acquire(&mut self, cost: &Cost, now: Instant) -> bool {
+        match cost {
+            Cost::Free => true,
+            Cost::Message { elevated: false } => {
+                let allowed = self.messages.try_take(1, now);
+                if allowed {
+                    self.elevated_messages.record(1, now);
+                }
+                allowed
+            }
+            Cost::Message { elevated: true } => {
+                let allowed = self.elevated_messages.try_take(1, now);
+                if allowed {
+                    self.messages.record(1, now);
+                }
+                allowed
+            }
+            Cost::Join(channels) => self.joins.try_take(*channels, now),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_burst_and_window() {
+        let now = Instant::now();
+        let mut log = SendLog::new(20, Duration::from_secs(30));
+        for _ in 0..20 {
+            assert!(log.try_take(1, now));
+        }
+        assert!(!log.try_take(1, now));
+        // nothing frees up before the burst is a whole window old
+        assert!(!log.try_take(1, now + Duration::from_millis(29_900)));
+        let later = now + Duration::from_secs(30);
+        for _ in 0..20 {
+            assert!(log.try_take(1, later));
+        }
+        assert!(!log.try_take(1, later));
+    }
+
+    #[test]
+    fn test_log_rolling_window() {
+        let start = Instant::now();
+        let mut log = SendLog::new(20, Duration::from_secs(30));
+        let mut sent = 0;
+        let mut now = start;
+        while now < start + Duration::from_secs(30) {
+            if log.try_take(1, now) {
+                sent += 1;
+            }
+            now += Duration::from_millis(10);
+        }
+        assert_eq!(sent, 20);
+    }
+
+    #[test]
+    fn test_limiter_messages() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(&RateLimits::default());
+        let normal = Cost::Message { elevated: false };
+        let elevated = Cost::Message { elevated: true };
+
+        for _ in 0..20 {
+            assert!(limiter.try_acquire(&normal, now));
+        }
+        assert!(!limiter.try_acquire(&normal, now));
+        // the messages above count against the elevated limit too
+        for _ in 0..80 {
+            assert!(limiter.try_acquire(&elevated, now));
+        }
+        assert!(!limiter.try_acquire(&elevated, now));
+        assert!(limiter.try_acquire(&Cost::Free, now));
+    }
+
+    #[test]
+    fn test_limiter_elevated_drains_normal() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(&RateLimits::default());
+        for _ in 0..50 {
+            assert!(limiter.try_acquire(&Cost::Message { elevated: true }, now));
+        }
+        assert!(!limiter.try_acquire(&Cost::Message { elevated: false }, now));
+    }
+
+    #[test]
+    fn test_limiter_joins() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(&RateLimits::default());
+        assert!(limiter.try_acquire(&Cost::Join(15), now));
+        assert!(!limiter.try_acquire(&Cost::Join(10), now));
+        assert!(limiter.try_acquire(&Cost::Join(5), now));
+        // more than one window's worth is never allowed, it has to be split
+        assert_eq!(limiter.max_joins(), 20);
+        assert!(!RateLimiter::new(&RateLimits::default()).try_acquire(&Cost::Join(50), now));
+
+        // 50 more in batches, after the 20 above
+        let mut batches = vec![20, 20, 10].into_iter().peekable();
+        let mut at = now;
+        while let Some(&channels) = batches.peek() {
+            if limiter.try_acquire(&Cost::Join(channels), at) {
+                batches.next();
+            } else {
+                at += Duration::from_millis(100);
+            }
+        }
+        assert!(at >= now + Duration::from_secs(30));
+    }
+}
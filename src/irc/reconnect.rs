@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+/// How the client retries after the connection drops.
+/// The delay before attempt n is `base_delay * 2^(n-1)`, capped at `max_delay`,
+/// then reduced by a random share of up to `jitter` (0.0 - 1.0).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    // None retries forever
+    pub max_attempts: Option<u32>,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: None,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn never() -> Self {
+        ReconnectPolicy {
+            max_attempts: Some(0),
+            ..Default::default()
+        }
+    }
+
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt <= max)
+    }
+
+    /// `random` is expected in 0.0..1.0.
+    pub fn delay(&self, attempt: u32, random: f64) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self.base_delay.saturating_mul(1 << exponent).min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0) * random.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 - jitter)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: Some(5),
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_delay_backoff() {
+        let policy = policy();
+        assert_eq!(policy.delay(1, 0.0), Duration::from_millis(500));
+        assert_eq!(policy.delay(2, 0.0), Duration::from_secs(1));
+        assert_eq!(policy.delay(4, 0.0), Duration::from_secs(4));
+        // capped
+        assert_eq!(policy.delay(6, 0.0), Duration::from_secs(10));
+        assert_eq!(policy.delay(100, 0.0), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_delay_jitter() {
+        let policy = policy();
+        assert_eq!(policy.delay(3, 1.0), Duration::from_secs(1));
+        assert_eq!(policy.delay(3, 0.5), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn test_allows() {
+        let policy = policy();
+        assert!(policy.allows(1));
+        assert!(policy.allows(5));
+        assert!(!policy.allows(6));
+        assert!(!ReconnectPolicy::never().allows(1));
+        assert!(ReconnectPolicy::default().allows(u32::MAX));
+    }
+}
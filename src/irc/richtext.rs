@@ -0,0 +1,195 @@
+use crate::irc::cheer::Cheermotes;
+use crate::irc::emotes::EmoteProvider;
+use crate::irc::protocol::{Message, ParseError, RichText};
+
+// left out of mentions and links when they end a sentence
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', '!', '?', ':', ';', ')', '"', '\''];
+
+// appends to the previous Text instead of starting a new one
+fn push_text(parts: &mut Vec<RichText>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match parts.last_mut() {
+        Some(RichText::Text(last)) => last.push_str(text),
+        _ => parts.push(RichText::Text(text.into())),
+    }
+}
+
+// `@name`, twitch names are letters, digits and underscores
+fn mention(word: &str) -> Option<(&str, &str)> {
+    let name = word.strip_prefix('@')?;
+    let end = name.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(name.len());
+    if end == 0 {
+        return None;
+    }
+    Some((&name[..end], &name[end..]))
+}
+
+fn link(word: &str) -> Option<(&str, &str)> {
+    if !(word.starts_with("http://") || word.starts_with("https://")) {
+        return None;
+    }
+    let url = word.trim_end_matches(TRAILING_PUNCTUATION);
+    Some((url, &word[url.len()..]))
+}
+
+// one whitespace separated word, `cheermotes` only if the message has bits
+fn push_word(parts: &mut Vec<RichText>, word: &str, providers: &[&dyn EmoteProvider], cheermotes: Option<&Cheermotes>) {
+    if let Some(emote) = providers.iter().find_map(|p| p.emote(word)) {
+        parts.push(RichText::Emote(emote));
+    } else if let Some((name, rest)) = mention(word) {
+        parts.push(RichText::Mention(name.into()));
+        push_text(parts, rest);
+    } else if let Some((url, rest)) = link(word) {
+        parts.push(RichText::Link(url.into()));
+        push_text(parts, rest);
+    } else if let Some(cheer) = cheermotes.and_then(|c| c.parse(word)) {
+        parts.push(RichText::Cheer { prefix: cheer.prefix, bits: cheer.bits });
+    } else {
+        push_text(parts, word);
+    }
+}
+
+fn tokenize(text: &str, providers: &[&dyn EmoteProvider], cheermotes: Option<&Cheermotes>, parts: &mut Vec<RichText>) {
+    let mut rest = text;
+    while !rest.is_empty() {
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if word_end > 0 {
+            push_word(parts, &rest[..word_end], providers, cheermotes);
+        }
+        rest = &rest[word_end..];
+        let space_end = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+        push_text(parts, &rest[..space_end]);
+        rest = &rest[space_end..];
+    }
+}
+
+// a zero-width emote right after another emote (whitespace between them aside) becomes its modifier
+fn attach_modifiers(parts: Vec<RichText>) -> Vec<RichText> {
+    let mut attached: Vec<RichText> = vec![];
+    for part in parts {
+        let RichText::Emote(emote) = part else {
+            attached.push(part);
+            continue;
+        };
+        if emote.zero_width {
+            let whitespace = matches!(attached.last(), Some(RichText::Text(t)) if t.trim().is_empty());
+            let base = attached.len().checked_sub(if whitespace { 2 } else { 1 });
+            if let Some(RichText::Emote(base)) = base.and_then(|i| attached.get_mut(i)) {
+                base.modifiers.push(emote);
+                if whitespace {
+                    attached.pop();
+                }
+                continue;
+            }
+        }
+        attached.push(RichText::Emote(emote));
+    }
+    attached
+}
+
+impl Message {
+    /// The text split into twitch emotes from the `emotes` tag, third-party emotes the providers
+    /// know, mentions, links and, in messages with a `bits` tag, cheers with the global cheermotes.
+    /// Zero-width emotes are attached to the emote before them.
+    pub fn rich_text(&self, providers: &[&dyn EmoteProvider]) -> Result<Vec<RichText>, ParseError> {
+        self.rich_text_with_cheermotes(providers, Cheermotes::global())
+    }
+
+    /// Like `rich_text`, for channels with their own cheermotes.
+    pub fn rich_text_with_cheermotes(&self, providers: &[&dyn EmoteProvider], cheermotes: &Cheermotes) -> Result<Vec<RichText>, ParseError> {
+        let cheermotes = Some(cheermotes).filter(|_| self.tags.contains_key("bits"));
+        let mut parts = vec![];
+        for part in self.emotes()? {
+            match part {
+                RichText::Text(text) => tokenize(&text, providers, cheermotes, &mut parts),
+                emote => parts.push(emote),
+            }
+        }
+        Ok(attach_modifiers(parts))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::emotes::EmoteSet;
+    use crate::irc::protocol::{parse_line, EmoteInfo, Provider};
+
+    const BTTV: &str = include_str!("../../testdata/emotes/bttv_channel.json");
+    const SEVENTV: &str = include_str!("../../testdata/emotes/7tv_user.json");
+
+    fn emote(id: &str, name: &str, provider: Provider) -> RichText {
+        RichText::Emote(EmoteInfo::new(id, name, provider))
+    }
+
+    #[test]
+    fn test_third_party_emotes() {
+        let set = EmoteSet::from_json(Provider::Bttv, BTTV).unwrap();
+        let line = "@emotes=25:14-18 :nick!user@host PRIVMSG #channel :catJAM  hello Kappa SourPls! SourPls";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.rich_text(&[&set]).unwrap(), vec![
+            emote("5e76d338d6581c3724c0f0b2", "catJAM", Provider::Bttv),
+            RichText::Text("  hello ".into()),
+            emote("25", "Kappa", Provider::Twitch),
+            RichText::Text(" SourPls! ".into()),
+            emote("566ca38765dbbdab32ec0560", "SourPls", Provider::Bttv),
+        ]);
+        // without providers it's the same as emotes()
+        assert_eq!(msg.rich_text(&[]).unwrap(), msg.emotes().unwrap());
+    }
+
+    #[test]
+    fn test_mentions_and_links() {
+        let line = "@emotes= :nick!user@host PRIVMSG #channel :@Someone_1, see https://example.com/a?b=c. @ or http:/nope";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.rich_text(&[]).unwrap(), vec![
+            RichText::Mention("Someone_1".into()),
+            RichText::Text(", see ".into()),
+            RichText::Link("https://example.com/a?b=c".into()),
+            RichText::Text(". @ or http:/nope".into()),
+        ]);
+    }
+
+    #[test]
+    fn test_cheers() {
+        let line = "@bits=150;emotes= :nick!user@host PRIVMSG #channel :Cheer100 nice Kappa50 abc1 forsenCheer5";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.rich_text(&[]).unwrap(), vec![
+            RichText::Cheer { prefix: "Cheer".into(), bits: 100 },
+            RichText::Text(" nice ".into()),
+            RichText::Cheer { prefix: "Kappa".into(), bits: 50 },
+            RichText::Text(" abc1 forsenCheer5".into()),
+        ]);
+        let cheermotes = Cheermotes::new(&["forsenCheer"]);
+        assert_eq!(msg.rich_text_with_cheermotes(&[], &cheermotes).unwrap().last(), Some(&RichText::Cheer {
+            prefix: "forsenCheer".into(),
+            bits: 5,
+        }));
+
+        // no bits tag, no cheers
+        let line = "@emotes= :nick!user@host PRIVMSG #channel :Cheer100";
+        let msg = parse_line(line).unwrap();
+        assert_eq!(msg.rich_text(&[]).unwrap(), vec![RichText::Text("Cheer100".into())]);
+    }
+
+    #[test]
+    fn test_zero_width_modifiers() {
+        let set = EmoteSet::from_json(Provider::SevenTv, SEVENTV).unwrap();
+        let line = "@emotes= :nick!user@host PRIVMSG #channel :peepoHappy RainTime hi RainTime";
+        let msg = parse_line(line).unwrap();
+
+        let mut happy = EmoteInfo::new("60ae958e229664e8667aea38", "peepoHappy", Provider::SevenTv);
+        let mut rain = EmoteInfo::new("60aea9a4ac03cad607f2c9e8", "RainTime", Provider::SevenTv);
+        rain.zero_width = true;
+        happy.modifiers.push(rain.clone());
+        // without an emote before it, it's shown on its own
+        assert_eq!(msg.rich_text(&[&set]).unwrap(), vec![
+            RichText::Emote(happy),
+            RichText::Text(" hi ".into()),
+            RichText::Emote(rain),
+        ]);
+    }
+}
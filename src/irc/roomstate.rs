@@ -0,0 +1,114 @@
+use crate::irc::protocol::{Command, Message};
+
+/// The chat settings of a channel, see https://dev.twitch.tv/docs/chat/irc/#roomstate-tags
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoomState {
+    pub room_id: Option<String>,
+    pub emote_only: bool,
+    // minutes a user must have followed, None when off
+    pub followers_only: Option<u32>,
+    // r9k, messages must be unique
+    pub unique_chat: bool,
+    // seconds between messages, None when off
+    pub slow: Option<u32>,
+    pub subs_only: bool,
+}
+
+/// A setting that changed, with its new value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RoomMode {
+    EmoteOnly(bool),
+    FollowersOnly(Option<u32>),
+    UniqueChat(bool),
+    Slow(Option<u32>),
+    SubsOnly(bool),
+}
+
+fn flag(value: &str) -> bool {
+    value == "1"
+}
+
+impl RoomState {
+    /// Applies the tags a ROOMSTATE carries. The one after a JOIN has all of them,
+    /// later ones only those that changed. Returns the modes that changed.
+    pub fn update(&mut self, msg: &Message) -> Vec<RoomMode> {
+        if msg.command != Command::RoomState {
+            return vec![];
+        }
+        let mut changes = vec![];
+        for (key, value) in msg.tags.iter() {
+            let mode = match key.as_str() {
+                "room-id" => {
+                    self.room_id = Some(value.clone());
+                    continue;
+                }
+                "emote-only" => RoomMode::EmoteOnly(flag(value)),
+                // -1 is off, 0 lets every follower chat
+                "followers-only" => RoomMode::FollowersOnly(value.parse().ok()),
+                "r9k" => RoomMode::UniqueChat(flag(value)),
+                "slow" => RoomMode::Slow(value.parse().ok().filter(|seconds| *seconds > 0)),
+                "subs-only" => RoomMode::SubsOnly(flag(value)),
+                _ => continue,
+            };
+            if self.apply(&mode) {
+                changes.push(mode);
+            }
+        }
+        changes
+    }
+
+    // false if the mode already had this value
+    fn apply(&mut self, mode: &RoomMode) -> bool {
+        fn set<T: PartialEq>(field: &mut T, value: T) -> bool {
+            let changed = *field != value;
+            *field = value;
+            changed
+        }
+        match mode.clone() {
+            RoomMode::EmoteOnly(on) => set(&mut self.emote_only, on),
+            RoomMode::FollowersOnly(minutes) => set(&mut self.followers_only, minutes),
+            RoomMode::UniqueChat(on) => set(&mut self.unique_chat, on),
+            RoomMode::Slow(seconds) => set(&mut self.slow, seconds),
+            RoomMode::SubsOnly(on) => set(&mut self.subs_only, on),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::protocol::parse_line;
+
+    #[test]
+    fn test_full_roomstate() {
+        let line = "@emote-only=0;followers-only=10;r9k=0;room-id=12345678;slow=0;subs-only=1 :tmi.twitch.tv ROOMSTATE #bar";
+        let mut state = RoomState::default();
+        state.update(&parse_line(line).unwrap());
+        assert_eq!(state, RoomState {
+            room_id: Some("12345678".into()),
+            emote_only: false,
+            followers_only: Some(10),
+            unique_chat: false,
+            slow: None,
+            subs_only: true,
+        });
+    }
+
+    #[test]
+    fn test_partial_roomstate() {
+        let line = "@emote-only=0;followers-only=-1;r9k=0;room-id=12345678;slow=0;subs-only=0 :tmi.twitch.tv ROOMSTATE #bar";
+        let mut state = RoomState::default();
+        assert!(state.update(&parse_line(line).unwrap()).is_empty());
+
+        let changes = state.update(&parse_line("@room-id=12345678;slow=30 :tmi.twitch.tv ROOMSTATE #bar").unwrap());
+        assert_eq!(changes, vec![RoomMode::Slow(Some(30))]);
+        let changes = state.update(&parse_line("@followers-only=0;room-id=12345678 :tmi.twitch.tv ROOMSTATE #bar").unwrap());
+        assert_eq!(changes, vec![RoomMode::FollowersOnly(Some(0))]);
+        assert_eq!(state.slow, Some(30));
+
+        // repeating the current value is no change
+        let changes = state.update(&parse_line("@room-id=12345678;slow=30 :tmi.twitch.tv ROOMSTATE #bar").unwrap());
+        assert!(changes.is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use std::path::Path;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+#[cfg(feature = "tls")]
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+#[cfg(feature = "tls")]
+use rustls::pki_types::{CertificateDer, ServerName};
+#[cfg(feature = "tls")]
+use rustls::pki_types::pem::PemObject;
+
+/// Which certificates the TLS transport trusts.
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub root_certificates: Vec<CertificateDer<'static>>,
+    // also trust the bundled Mozilla roots
+    pub webpki_roots: bool,
+}
+
+// without the feature TLS can't be selected, `Option<TlsConfig>` is always `None`.
+#[cfg(not(feature = "tls"))]
+#[derive(Debug, Clone)]
+pub enum TlsConfig {}
+
+#[cfg(feature = "tls")]
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            root_certificates: vec![],
+            webpki_roots: true,
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+impl TlsConfig {
+    /// Trusts only the certificates in the given PEM data, e.g. a self-signed test CA.
+    pub fn from_pem(pem: &[u8]) -> io::Result<TlsConfig> {
+        let root_certificates = CertificateDer::pem_slice_iter(pem)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(TlsConfig {
+            root_certificates,
+            webpki_roots: false,
+        })
+    }
+
+    pub fn from_pem_file(path: impl AsRef<Path>) -> io::Result<TlsConfig> {
+        TlsConfig::from_pem(&std::fs::read(path)?)
+    }
+
+    fn client_config(&self) -> io::Result<Arc<ClientConfig>> {
+        let mut roots = RootCertStore::empty();
+        if self.webpki_roots {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+        for cert in self.root_certificates.iter() {
+            roots.add(cert.clone()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Arc::new(config))
+    }
+}
+
+fn tcp_connect(host: &str, port: u16, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect((host, port));
+    };
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", host));
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+pub(crate) enum Transport {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Transport {
+    pub(crate) fn connect(host: &str, port: u16, tls: Option<&TlsConfig>, timeout: Option<Duration>) -> io::Result<Transport> {
+        match tls {
+            None => Ok(Transport::Plain(tcp_connect(host, port, timeout)?)),
+            #[cfg(feature = "tls")]
+            Some(config) => Transport::connect_tls(host, port, config, timeout),
+            #[cfg(not(feature = "tls"))]
+            Some(config) => match *config {},
+        }
+    }
+
+    /// Connects and finishes the TLS handshake, so a read timeout set afterwards
+    /// can't interrupt it.
+    #[cfg(feature = "tls")]
+    fn connect_tls(host: &str, port: u16, config: &TlsConfig, timeout: Option<Duration>) -> io::Result<Transport> {
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut conn = ClientConnection::new(config.client_config()?, server_name)
+            .map_err(io::Error::other)?;
+        let mut sock = tcp_connect(host, port, timeout)?;
+        sock.set_read_timeout(timeout)?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut sock)?;
+        }
+        Ok(Transport::Tls(Box::new(StreamOwned::new(conn, sock))))
+    }
+
+    fn tcp_stream(&self) -> &TcpStream {
+        match self {
+            Transport::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp_stream().set_read_timeout(timeout)
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+    use rustls::{ServerConfig, ServerConnection};
+    use rustls::pki_types::PrivateKeyDer;
+
+    const CA: &str = include_str!("../../testdata/tls/ca.pem");
+    const CERT: &str = include_str!("../../testdata/tls/localhost.pem");
+    const KEY: &str = include_str!("../../testdata/tls/localhost.key");
+
+    // a TLS server that answers the first line it receives with "PONG <rest of line>".
+    fn spawn_server() -> u16 {
+        let certs = CertificateDer::pem_slice_iter(CERT.as_bytes()).collect::<Result<Vec<_>, _>>().unwrap();
+        let key = PrivateKeyDer::from_pem_slice(KEY.as_bytes()).unwrap();
+        let config = Arc::new(ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(config).unwrap();
+            let mut stream = StreamOwned::new(conn, sock);
+            let mut line = String::new();
+            // fails when the client rejects our certificate
+            if BufReader::new(&mut stream).read_line(&mut line).is_ok() {
+                let reply = line.replacen("PING", "PONG", 1);
+                let _ = stream.write_all(reply.as_bytes());
+                let _ = stream.flush();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_tls_custom_root() {
+        let port = spawn_server();
+        let config = TlsConfig::from_pem(CA.as_bytes()).unwrap();
+        let mut transport = Transport::connect_tls("localhost", port, &config, None).unwrap();
+        transport.write_all(b"PING :tmi.twitch.tv\r\n").unwrap();
+
+        let mut line = String::new();
+        BufReader::new(&mut transport).read_line(&mut line).unwrap();
+        assert_eq!(line, "PONG :tmi.twitch.tv\r\n");
+    }
+
+    #[test]
+    fn test_tls_untrusted() {
+        let port = spawn_server();
+        // only the bundled roots, which don't know the test CA
+        let config = TlsConfig::default();
+        assert!(Transport::connect_tls("localhost", port, &config, None).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use std::str::FromStr;
+use crate::irc::protocol::{Command, Message};
+
+/// `msg-param-sub-plan`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubTier {
+    Prime,
+    Tier1,
+    Tier2,
+    Tier3,
+    Other(String),
+}
+
+impl SubTier {
+    pub fn from_plan(plan: &str) -> SubTier {
+        match plan {
+            "Prime" => SubTier::Prime,
+            "1000" => SubTier::Tier1,
+            "2000" => SubTier::Tier2,
+            "3000" => SubTier::Tier3,
+            _ => SubTier::Other(plan.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubInfo {
+    pub tier: SubTier,
+    pub plan_name: String,
+    pub cumulative_months: u32,
+    // only when the user shares their streak
+    pub streak_months: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GiftRecipient {
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
+}
+
+/// What a USERNOTICE announces, decided by its `msg-id` and filled from the `msg-param-*` tags.
+/// The user that triggered it is in the usual `login`/`display-name` tags of the message.
+/// See https://dev.twitch.tv/docs/chat/irc/#usernotice-tags
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserNoticeEvent {
+    Sub(SubInfo),
+    Resub(SubInfo),
+    SubGift {
+        tier: SubTier,
+        recipient: GiftRecipient,
+        // months the recipient has subscribed in total
+        months: u32,
+        // months gifted at once
+        gift_months: u32,
+    },
+    SubMysteryGift {
+        tier: SubTier,
+        count: u32,
+        // all gifts of the sender in this channel, unknown for anonymous gifts
+        sender_total: Option<u32>,
+    },
+    GiftPaidUpgrade {
+        // None when the original gift was anonymous
+        gifter_login: Option<String>,
+    },
+    PrimePaidUpgrade {
+        tier: SubTier,
+    },
+    Raid {
+        login: String,
+        display_name: String,
+        viewer_count: u32,
+    },
+    Unraid,
+    Announcement {
+        // PRIMARY, BLUE, GREEN, ORANGE or PURPLE
+        color: String,
+    },
+    BitsBadgeTier {
+        threshold: u32,
+    },
+    Ritual {
+        name: String,
+    },
+    ViewerMilestone {
+        // e.g. watch-streak
+        category: String,
+        value: u32,
+        reward: Option<u32>,
+    },
+    // anything not listed above, the msg-id kept verbatim
+    Other(String),
+}
+
+impl Message {
+    /// A `msg-param-` tag, e.g. `msg_param("cumulative-months")`.
+    pub fn msg_param(&self, name: &str) -> Option<&String> {
+        self.tags.get(&format!("msg-param-{}", name))
+    }
+
+    fn msg_param_parsed<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.msg_param(name).and_then(|value| value.parse().ok())
+    }
+
+    fn msg_param_string(&self, name: &str) -> String {
+        self.msg_param(name).cloned().unwrap_or_default()
+    }
+
+    fn sub_tier(&self) -> SubTier {
+        SubTier::from_plan(&self.msg_param_string("sub-plan"))
+    }
+
+    fn sub_info(&self) -> SubInfo {
+        let shares_streak = self.msg_param("should-share-streak").is_some_and(|v| v == "1");
+        SubInfo {
+            tier: self.sub_tier(),
+            plan_name: self.msg_param_string("sub-plan-name"),
+            cumulative_months: self.msg_param_parsed("cumulative-months").unwrap_or(1),
+            streak_months: self.msg_param_parsed("streak-months").filter(|_| shares_streak),
+        }
+    }
+
+    /// `None` for anything but a USERNOTICE with a `msg-id`. Missing `msg-param-*` tags become
+    /// empty strings or zero, except month counts, which become one since a sub lasts at least a month.
+    pub fn user_notice_event(&self) -> Option<UserNoticeEvent> {
+        if self.command != Command::UserNotice {
+            return None;
+        }
+        let event = match self.tags.get("msg-id")?.as_str() {
+            "sub" => UserNoticeEvent::Sub(self.sub_info()),
+            "resub" => UserNoticeEvent::Resub(self.sub_info()),
+            "subgift" | "anonsubgift" => UserNoticeEvent::SubGift {
+                tier: self.sub_tier(),
+                recipient: GiftRecipient {
+                    id: self.msg_param_string("recipient-id"),
+                    login: self.msg_param_string("recipient-user-name"),
+                    display_name: self.msg_param_string("recipient-display-name"),
+                },
+                months: self.msg_param_parsed("months").unwrap_or(1),
+                gift_months: self.msg_param_parsed("gift-months").unwrap_or(1),
+            },
+            "submysterygift" | "anonsubmysterygift" => UserNoticeEvent::SubMysteryGift {
+                tier: self.sub_tier(),
+                count: self.msg_param_parsed("mass-gift-count").unwrap_or_default(),
+                sender_total: self.msg_param_parsed("sender-count").filter(|count| *count > 0),
+            },
+            "giftpaidupgrade" => UserNoticeEvent::GiftPaidUpgrade {
+                gifter_login: self.msg_param("sender-login").cloned(),
+            },
+            "anongiftpaidupgrade" => UserNoticeEvent::GiftPaidUpgrade { gifter_login: None },
+            "primepaidupgrade" => UserNoticeEvent::PrimePaidUpgrade { tier: self.sub_tier() },
+            "raid" => UserNoticeEvent::Raid {
+                login: self.msg_param_string("login"),
+                display_name: self.msg_param_string("displayName"),
+                viewer_count: self.msg_param_parsed("viewerCount").unwrap_or_default(),
+            },
+            "unraid" => UserNoticeEvent::Unraid,
+            "announcement" => UserNoticeEvent::Announcement {
+                color: self.msg_param("color").cloned().unwrap_or_else(|| "PRIMARY".into()),
+            },
+            "bitsbadgetier" => UserNoticeEvent::BitsBadgeTier {
+                threshold: self.msg_param_parsed("threshold").unwrap_or_default(),
+            },
+            "ritual" => UserNoticeEvent::Ritual { name: self.msg_param_string("ritual-name") },
+            "viewermilestone" => UserNoticeEvent::ViewerMilestone {
+                category: self.msg_param_string("category"),
+                value: self.msg_param_parsed("value").unwrap_or_default(),
+                reward: self.msg_param_parsed("copoReward"),
+            },
+            other => UserNoticeEvent::Other(other.into()),
+        };
+        Some(event)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::protocol::parse_line;
+
+    #[test]
+    fn test_resub() {
+        let line = r"@badge-info=subscriber/8;badges=subscriber/6;display-name=ronni;login=ronni;msg-id=resub;msg-param-cumulative-months=8;msg-param-should-share-streak=1;msg-param-streak-months=2;msg-param-sub-plan=Prime;msg-param-sub-plan-name=Prime;system-msg=ronni\shas\ssubscribed\sfor\s8\smonths!;tmi-sent-ts=1507246572675 :tmi.twitch.tv USERNOTICE #dallas :Great stream -- keep it up!";
+        let event = parse_line(line).unwrap().user_notice_event().unwrap();
+        assert_eq!(event, UserNoticeEvent::Resub(SubInfo {
+            tier: SubTier::Prime,
+            plan_name: "Prime".into(),
+            cumulative_months: 8,
+            streak_months: Some(2),
+        }));
+    }
+
+    #[test]
+    fn test_sub_hidden_streak() {
+        let line = "@msg-id=sub;msg-param-cumulative-months=1;msg-param-should-share-streak=0;msg-param-streak-months=0;msg-param-sub-plan=2000;msg-param-sub-plan-name=Channel\\sSubscription :tmi.twitch.tv USERNOTICE #dallas";
+        match parse_line(line).unwrap().user_notice_event() {
+            Some(UserNoticeEvent::Sub(info)) => {
+                assert_eq!(info.tier, SubTier::Tier2);
+                assert_eq!(info.plan_name, "Channel Subscription");
+                assert_eq!(info.streak_months, None);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_subgift() {
+        let line = r"@display-name=TWW2;login=tww2;msg-id=subgift;msg-param-months=1;msg-param-recipient-display-name=Mr_Woodchuck;msg-param-recipient-id=55554444;msg-param-recipient-user-name=mr_woodchuck;msg-param-sub-plan-name=House\sof\sNyoro~n;msg-param-sub-plan=1000 :tmi.twitch.tv USERNOTICE #forstycup";
+        let event = parse_line(line).unwrap().user_notice_event().unwrap();
+        assert_eq!(event, UserNoticeEvent::SubGift {
+            tier: SubTier::Tier1,
+            recipient: GiftRecipient {
+                id: "55554444".into(),
+                login: "mr_woodchuck".into(),
+                display_name: "Mr_Woodchuck".into(),
+            },
+            months: 1,
+            gift_months: 1,
+        });
+    }
+
+    #[test]
+    fn test_raid() {
+        let line = r"@display-name=TestChannel;login=testchannel;msg-id=raid;msg-param-displayName=TestChannel;msg-param-login=testchannel;msg-param-viewerCount=15;system-msg=15\sraiders\sfrom\sTestChannel\shave\sjoined\n! :tmi.twitch.tv USERNOTICE #othertestchannel";
+        let event = parse_line(line).unwrap().user_notice_event().unwrap();
+        assert_eq!(event, UserNoticeEvent::Raid {
+            login: "testchannel".into(),
+            display_name: "TestChannel".into(),
+            viewer_count: 15,
+        });
+    }
+
+    #[test]
+    fn test_announcement_and_other() {
+        let line = "@msg-id=announcement;msg-param-color=BLUE :tmi.twitch.tv USERNOTICE #dallas :Hello";
+        let event = parse_line(line).unwrap().user_notice_event().unwrap();
+        assert_eq!(event, UserNoticeEvent::Announcement { color: "BLUE".into() });
+
+        let line = "@msg-id=sharedchatnotice :tmi.twitch.tv USERNOTICE #dallas";
+        let event = parse_line(line).unwrap().user_notice_event().unwrap();
+        assert_eq!(event, UserNoticeEvent::Other("sharedchatnotice".into()));
+
+        let msg = parse_line("@msg-id=raid :tmi.twitch.tv NOTICE #dallas :hi").unwrap();
+        assert_eq!(msg.user_notice_event(), None);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::irc::badge::Badge;
+use crate::irc::protocol::{Command, Message};
+
+/// Our own identity as twitch reports it, in GLOBALUSERSTATE after login and in USERSTATE
+/// per channel after joining or sending a message there.
+/// See https://dev.twitch.tv/docs/chat/irc/#userstate-tags
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserState {
+    // only in GLOBALUSERSTATE
+    pub user_id: Option<String>,
+    pub display_name: Option<String>,
+    // #RRGGBB, None if never set
+    pub color: Option<String>,
+    pub badges: Vec<Badge>,
+    pub badge_info: Vec<Badge>,
+    pub emote_sets: Vec<String>,
+    pub broadcaster: bool,
+    pub moderator: bool,
+    pub vip: bool,
+    pub subscriber: bool,
+}
+
+fn non_empty(value: Option<&String>) -> Option<String> {
+    value.filter(|v| !v.is_empty()).cloned()
+}
+
+impl UserState {
+    /// `None` for anything but a USERSTATE or GLOBALUSERSTATE.
+    pub fn from_message(msg: &Message) -> Option<UserState> {
+        if !matches!(msg.command, Command::UserState | Command::GlobalUserState) {
+            return None;
+        }
+        Some(UserState {
+            user_id: non_empty(msg.tags.get("user-id")),
+            display_name: non_empty(msg.tags.get("display-name")),
+            color: non_empty(msg.tags.get("color")),
+            badges: msg.badges(),
+            badge_info: msg.badge_info(),
+            emote_sets: msg.tags.get("emote-sets")
+                .map(|sets| sets.split(',').filter(|set| !set.is_empty()).map(|set| set.into()).collect())
+                .unwrap_or_default(),
+            broadcaster: msg.is_broadcaster(),
+            moderator: msg.is_moderator(),
+            vip: msg.is_vip(),
+            subscriber: msg.is_subscriber(),
+        })
+    }
+
+    /// Broadcasters, moderators and VIPs may send more messages.
+    pub fn has_elevated_rate_limit(&self) -> bool {
+        self.broadcaster || self.moderator || self.vip
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::protocol::parse_line;
+
+    #[test]
+    fn test_global_user_state() {
+        let line = "@badge-info=subscriber/8;badges=subscriber/6;color=#0D4200;display-name=dallas;emote-sets=0,33,50,237,793,2126,3517,4578,5569,9400,10337,12239;turbo=0;user-id=12345678;user-type=admin :tmi.twitch.tv GLOBALUSERSTATE";
+        let state = UserState::from_message(&parse_line(line).unwrap()).unwrap();
+        assert_eq!(state.user_id.as_deref(), Some("12345678"));
+        assert_eq!(state.display_name.as_deref(), Some("dallas"));
+        assert_eq!(state.color.as_deref(), Some("#0D4200"));
+        assert_eq!(state.emote_sets.len(), 12);
+        assert!(state.subscriber);
+        assert!(!state.has_elevated_rate_limit());
+    }
+
+    #[test]
+    fn test_user_state() {
+        let line = "@badge-info=;badges=moderator/1;color=;display-name=bot;emote-sets=0;mod=1;subscriber=0;user-type=mod :tmi.twitch.tv USERSTATE #dallas";
+        let state = UserState::from_message(&parse_line(line).unwrap()).unwrap();
+        assert_eq!(state.user_id, None);
+        assert_eq!(state.color, None);
+        assert!(state.moderator);
+        assert!(state.has_elevated_rate_limit());
+
+        assert_eq!(UserState::from_message(&parse_line("PING :tmi.twitch.tv").unwrap()), None);
+    }
+}
@@ -1,3 +1,7 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use unicode_segmentation::UnicodeSegmentation;
+
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, PartialEq)]
@@ -14,7 +18,7 @@ pub struct Color {
 }
 
 fn hex_to_byte(hex: &str) -> Result<u8> {
-    if hex.len() < 1 || hex.len() > 2 {
+    if hex.is_empty() || hex.len() > 2 {
         return Err(Error::ParseError(hex.into()));
     }
 
@@ -36,12 +40,9 @@ impl Color {
         Color { red: r, green: g, blue: b }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(hex: &str) -> Result<Color> {
-        let tmphex = if hex.starts_with("#") {
-            &hex[1..]
-        } else {
-            &hex
-        };
+        let tmphex = hex.strip_prefix("#").unwrap_or(hex);
 
         let rgb = if tmphex.len() == 6 {
             Ok((&tmphex[0..2], &tmphex[2..4], &tmphex[4..6]))
@@ -58,6 +59,7 @@ impl Color {
         Ok(Color::new(r, g, b))
     }
 
+    #[allow(clippy::ptr_arg)]
     pub fn from_string(hex: &String) -> Result<Color> {
         Color::from_str(hex.as_str())
     }
@@ -81,11 +83,143 @@ impl Color {
     }
 }
 
+/// Lowercase channel name with a leading `#`.
+pub fn normalize_channel(channel: &str) -> String {
+    let channel = channel.trim().to_lowercase();
+    if channel.starts_with('#') {
+        channel
+    } else {
+        format!("#{}", channel)
+    }
+}
+
+// alternating runs of whitespace and of everything else
+fn whitespace_runs(text: &str) -> Vec<&str> {
+    let mut runs = vec![];
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        let end = chars.peek().map_or(text.len(), |(i, _)| *i);
+        if chars.peek().is_none_or(|(_, next)| next.is_whitespace() != c.is_whitespace()) {
+            runs.push(&text[start..end]);
+            start = end;
+        }
+    }
+    runs
+}
+
+/// Splits `text` into pieces of at most `max_chars` characters. Pieces end between words, so
+/// emote names stay whole. A word longer than a piece is cut between graphemes, never inside
+/// a character. Every piece but the last ends with a space and `marker`.
+pub fn split_message(text: &str, max_chars: usize, marker: Option<&str>) -> Vec<String> {
+    let text = text.trim();
+    if text.chars().count() <= max_chars {
+        return vec![text.into()];
+    }
+    let suffix = marker.map(|m| format!(" {}", m)).unwrap_or_default();
+    let budget = max_chars.saturating_sub(suffix.chars().count()).max(1);
+
+    let mut pieces = vec![];
+    let mut piece = String::new();
+    let mut piece_len = 0;
+    let mut pos = 0;
+    for run in whitespace_runs(text) {
+        let len = run.chars().count();
+        pos += run.len();
+        if piece_len + len > budget {
+            // the last piece doesn't need room for the marker
+            let rest = &text[pos - run.len()..];
+            if piece_len + rest.chars().count() <= max_chars {
+                piece.push_str(rest);
+                break;
+            }
+            if !piece.trim().is_empty() {
+                pieces.push(piece.trim().to_string());
+            }
+            piece.clear();
+            piece_len = 0;
+            if run.trim().is_empty() {
+                continue;
+            }
+        }
+        if len <= budget {
+            piece.push_str(run);
+            piece_len += len;
+            continue;
+        }
+        for grapheme in run.graphemes(true) {
+            let len = grapheme.chars().count();
+            if piece_len + len > budget && !piece.is_empty() {
+                pieces.push(std::mem::take(&mut piece));
+                piece_len = 0;
+            }
+            piece.push_str(grapheme);
+            piece_len += len;
+        }
+    }
+    if !piece.trim().is_empty() {
+        pieces.push(piece.trim().to_string());
+    }
+
+    let last = pieces.len() - 1;
+    for piece in pieces[..last].iter_mut() {
+        piece.push_str(&suffix);
+    }
+    pieces
+}
+
+/// A random number in 0.0..1.0, good enough for jitter. Not for anything security related.
+pub fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_normalize_channel() {
+        assert_eq!(normalize_channel("Forsen"), "#forsen");
+        assert_eq!(normalize_channel("#forsen"), "#forsen");
+        assert_eq!(normalize_channel(" #Forsen "), "#forsen");
+    }
+
+    #[test]
+    fn test_split_message_short() {
+        assert_eq!(split_message(" hello world ", 11, Some("...")), vec!["hello world"]);
+    }
+
+    #[test]
+    fn test_split_message_words() {
+        assert_eq!(split_message("Kappa Keepo  PogChamp LUL", 12, None), vec!["Kappa Keepo", "PogChamp LUL"]);
+        assert_eq!(split_message("Kappa Keepo PogChamp LUL", 12, Some("(…)")), vec!["Kappa (…)", "Keepo (…)", "PogChamp LUL"]);
+    }
+
+    #[test]
+    fn test_split_message_counts_characters() {
+        // 3 bytes each, but one character
+        let text = "日本語 日本語 日本語";
+        assert_eq!(split_message(text, 7, None), vec!["日本語 日本語", "日本語"]);
+    }
+
+    #[test]
+    fn test_split_message_long_word() {
+        assert_eq!(split_message("abcdefgh", 3, None), vec!["abc", "def", "gh"]);
+        // e + combining acute accent is one grapheme of two characters
+        let text = "ab\u{65}\u{301}cd";
+        let pieces = split_message(text, 3, None);
+        assert_eq!(pieces, vec!["ab", "\u{65}\u{301}c", "d"]);
+        // family emoji made of joined code points stays whole even if it doesn't fit
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert_eq!(split_message(&format!("x{}", family), 4, None), vec!["x".to_string(), family.to_string()]);
+    }
+
     #[test]
     fn test_color_simple() {
         let color = Color::from_str("#Ff8000");
@@ -1,53 +1,103 @@
-use std::fs::{OpenOptions};
+use std::fs::{File, OpenOptions};
 use std::io::{Seek, SeekFrom, Write};
-use irc::client::Client;
-use crate::irc::protocol::{Command, RichText};
-use crate::irc::utils::Color;
+use twitcher::irc::client::Client;
+use twitcher::irc::cheer::Cheermotes;
+use twitcher::irc::emotes::EmoteSet;
+use twitcher::irc::event::Event;
+use twitcher::irc::protocol::{Command, Message, RichText};
+use twitcher::irc::usernotice::UserNoticeEvent;
+use twitcher::irc::utils::Color;
 
-mod irc;
+fn write_banner(file: &mut File, text: &str) {
+    let border = "-".repeat(text.len() + 4);
+    file.write_all(format!("{}\n- {} -\n{}\n", border, text, border).as_bytes()).unwrap();
+}
+
+// `/me` messages as `* name text`, like other clients show them
+fn print_chat(msg: &Message, name: &str, text: &str) {
+    let place = if msg.is_channel_message() { msg.params[0].as_str() } else { "(private)" };
+    if msg.is_action() {
+        println!("{} * {} {}", place, name, text);
+    } else {
+        println!("{} <{}> {}", place, name, text);
+    }
+}
 
 fn main() {
     dotenv::dotenv().ok();
     let token = std::env::var("OAUTH_TOKEN").expect("OAUTH_TOKEN is missing!");
     let nickname = std::env::var("NICKNAME").expect("NICKNAME is missing!");
     let channel_string = std::env::var("CHANNELS").expect("CHANNELS is missing!");
-    let channels = channel_string.split(",").map(|c| {
-        if c.starts_with("#") {
-            c.into()
-        } else {
-            format!("#{}", c)
-        }
-    }).collect::<Vec<String>>();
+    let channels = channel_string.split(",").collect::<Vec<&str>>();
+    // the BTTV/FFZ/7TV API responses saved as bttv*.json, ffz*.json and 7tv*.json
+    let emote_set = match std::env::var("EMOTE_DIR") {
+        Ok(dir) => EmoteSet::from_dir(dir).expect("Failed to load emotes"),
+        Err(_) => EmoteSet::default(),
+    };
+    // the response of the Helix bits/cheermotes endpoint, for channels with their own cheermotes
+    let cheermotes = match std::env::var("CHEERMOTES") {
+        Ok(path) => Cheermotes::from_file(path).expect("Failed to load cheermotes"),
+        Err(_) => Cheermotes::default(),
+    };
 
     let mut file = OpenOptions::new()
         .read(true)
         .write(true)
         .create(true)
+        .truncate(false)
         .open("logs.txt").unwrap();
 
     file.seek(SeekFrom::End(0)).unwrap();
-    file.write("----------------\n".as_bytes()).unwrap();
-    file.write("- Reconnecting -\n".as_bytes()).unwrap();
-    file.write("----------------\n".as_bytes()).unwrap();
+    write_banner(&mut file, "Starting");
 
-    let mut client = Client::new(&token, &nickname);
-    client.connect();
+    let builder = Client::builder(&token, &nickname).channels(&channels);
+    #[cfg(feature = "tls")]
+    let builder = builder.tls(Some(twitcher::irc::transport::TlsConfig::default()));
+    let mut client = builder.build();
+    client.connect().expect("Failed to connect");
 
-    for msg in client.iter() {
+    for event in client.iter() {
+        let msg = match event {
+            Event::Message(msg) => msg,
+            Event::Connected => {
+                println!("Connected");
+                continue;
+            }
+            Event::Disconnected => {
+                println!("Disconnected");
+                continue;
+            }
+            Event::Error(e) => {
+                println!("Error: {}", e);
+                continue;
+            }
+            Event::Reconnecting(attempt) => {
+                println!("Reconnecting (attempt {})", attempt);
+                write_banner(&mut file, "Reconnecting");
+                continue;
+            }
+            Event::RoomModeChanged(channel, mode) => {
+                println!("{} mode changed: {:?}", channel, mode);
+                continue;
+            }
+            Event::UserJoined(channel, user) => {
+                println!("{} joined {}", user, channel);
+                continue;
+            }
+            Event::UserLeft(channel, user) => {
+                println!("{} left {}", user, channel);
+                continue;
+            }
+        };
         match msg.command {
             Command::Part => {}
             Command::Join => {}
             _ => {
-                file.write(msg.original_line().as_bytes()).unwrap();
-                file.write("\n".as_bytes()).unwrap();
+                file.write_all(msg.original_line().as_bytes()).unwrap();
+                file.write_all("\n".as_bytes()).unwrap();
             }
         }
         match msg.command {
-            Command::Ready => {
-                for channel in channels.iter() {
-                    client.send_line(&format!("JOIN {}", channel)).unwrap();
-                }
-            }
             Command::Privmsg if msg.is_channel_message() => {
                 let display_name = msg.display_name().unwrap();
                 let colored_name = if let Some(hex) = msg.tags.get("color") {
@@ -59,7 +109,7 @@ fn main() {
                     display_name.into()
                 };
 
-                if let Ok(emotes) = msg.emotes() {
+                if let Ok(emotes) = msg.rich_text_with_cheermotes(&[&emote_set], &cheermotes) {
                     let text_items = emotes.iter().map(|e| {
                         match e {
                             RichText::Text(s) => s.into(),
@@ -67,26 +117,38 @@ fn main() {
                                 let mut s = String::new();
                                 s.push_str("\x1b[32m[");
                                 s.push_str(&e.emote);
+                                for modifier in e.modifiers.iter() {
+                                    s.push('+');
+                                    s.push_str(&modifier.emote);
+                                }
                                 s.push_str("]\x1b[0m");
                                 s
                             }
+                            RichText::Mention(name) => format!("\x1b[1m@{}\x1b[0m", name),
+                            RichText::Link(url) => format!("\x1b[4m{}\x1b[0m", url),
+                            RichText::Cheer { prefix, bits } => format!("\x1b[35m{}{}\x1b[0m", prefix, bits),
                         }
                     }).collect::<Vec<String>>();
                     let text_message = text_items.join("");
-                    println!("{} <{}> {}", msg.params[0], colored_name, text_message);
+                    print_chat(&msg, &colored_name, &text_message);
                 } else {
-                    println!("{} <{}> {}", msg.params[0], colored_name, msg.params[1]);
+                    print_chat(&msg, &colored_name, msg.text().unwrap());
                 }
             }
             Command::Privmsg if msg.is_private_message() => {
-                println!("(private) <{}> {}", msg.display_name().unwrap(), msg.params[1]);
+                print_chat(&msg, msg.display_name().unwrap(), msg.text().unwrap());
             }
             Command::EndOfNames => {
-                println!("Joined: {}", msg.params[1]);
+                let chatters = client.chatters(&msg.params[1]).map_or(0, |chatters| chatters.len());
+                println!("Joined: {} ({} chatters)", msg.params[1], chatters);
             }
             Command::UserNotice => {
-                if let Some(system_msg) = msg.tags.get("system-msg") {
-                    println!("System: {}", system_msg.replace("\\s", " "));
+                if let Some(UserNoticeEvent::Announcement { .. }) = msg.user_notice_event() {
+                    // the prefix is tmi.twitch.tv, the sender is only in the tags
+                    let sender = msg.tags.get("display-name").map_or("", |name| name);
+                    println!("{} [announcement] <{}> {}", msg.params[0], sender, msg.params.get(1).map_or("", |text| text));
+                } else if let Some(system_msg) = msg.tags.get("system-msg") {
+                    println!("System: {}", system_msg);
                 }
             }
             _ => ()